@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+/**
+ * Exponential backoff with jitter, the delay doubles after every failure up to `max`, and a random half of it is
+ * dropped so a room full of boards doesn't hammer the server at the same moment after it comes back.
+ */
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            current: min,
+        }
+    }
+
+    /**
+     * The delay before the next attempt, `random` is any random number, e.g. from the hardware RNG.
+     */
+    pub fn next_delay(&mut self, random: u32) -> Duration {
+        let half = self.current / 2;
+        let jitter = Duration::from_millis(random as u64 % (half.as_millis() as u64 + 1));
+        let ret = self.current - half + jitter;
+        self.current = (self.current * 2).min(self.max);
+        ret
+    }
+
+    /**
+     * Starts over from `min`, after a successful connection.
+     */
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Backoff;
+
+    #[test]
+    fn doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        // Without jitter the delay is half of the current step
+        let delays: Vec<u64> = (0..5)
+            .map(|_| backoff.next_delay(0).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![500, 1000, 2000, 2500, 2500]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(0), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn jitter() {
+        let mut backoff = Backoff::new(Duration::from_secs(4), Duration::from_secs(60));
+        assert_eq!(backoff.next_delay(2000), Duration::from_secs(4));
+        backoff.reset();
+        assert_eq!(backoff.next_delay(2001), Duration::from_secs(2));
+        for random in [0, 1, 999, u32::MAX] {
+            let delay = backoff.next_delay(random);
+            assert!(delay <= Duration::from_secs(60));
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use super::DeviceOptions;
+
 pub trait Actuator {
     fn connected(&mut self);
 
@@ -25,11 +27,20 @@ pub trait Actuator {
 
     fn set_clipboard(&mut self, data: Vec<u8>);
 
-    fn set_options(&mut self, heartbeat: u32);
+    /**
+     * Text to be published as the clipboard of this screen, polled by the client after every packet.
+     */
+    fn take_clipboard(&mut self) -> Option<Vec<u8>>;
+
+    fn set_options(&mut self, options: DeviceOptions);
 
     fn reset_options(&mut self);
 
-    fn enter(&mut self);
+    /**
+     * The cursor entered the screen at `x` and `y`, in the absolute range like `set_cursor_position`, with the
+     * modifiers and locks of the server in `mask`.
+     */
+    fn enter(&mut self, x: u16, y: u16, mask: u16);
 
     fn leave(&mut self);
 
@@ -0,0 +1,276 @@
+use std::{
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+
+use crate::barrier::packet_stream::ReadTimeout;
+
+use super::{
+    clipboard::{clipboard_chunks, ClipboardAssembler, MAX_CLIPBOARD_SIZE},
+    take::Take,
+    Actuator, ConnectionError, Greeting, Packet, PacketError, PacketReader, PacketStream,
+    PacketWriter, ProtocolVersion, Watchdog,
+};
+
+// Barrier sends a keep alive every 3 seconds by default, and gives up after missing 3 of them
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(3);
+const KEEP_ALIVES_UNTIL_DEATH: u32 = 3;
+
+/**
+ * Opens the TCP connection to the server, TLS or any other transport can be layered on top of it before `start`.
+ */
+pub fn connect(addr: &str, port: u16) -> Result<TcpStream, ConnectionError> {
+    let stream = TcpStream::connect((addr, port))?;
+    // Turn off Nagle, this may not be available on ESP-IDF, so ignore the error.
+    stream.set_nodelay(true).ok();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).ok();
+    Ok(stream)
+}
+
+pub fn start<S: PacketReader + PacketWriter + ReadTimeout, A: Actuator, W: Watchdog>(
+    mut stream: S,
+    device_name: &str,
+    actor: &mut A,
+    wd: &mut W,
+) -> Result<(), ConnectionError> {
+    let screen_size: (u16, u16) = actor.get_screen_size();
+
+    let (greeting, server_version) = {
+        let size = stream.read_packet_size()?;
+        let mut hello = Take::new(&mut stream, size as u64);
+        let name: [u8; 7] = hello.read_bytes_fixed()?;
+        let greeting = Greeting::parse(&name).ok_or(PacketError::FormatError)?;
+        let major = hello.read_u16()?;
+        let minor = hello.read_u16()?;
+        // Newer servers may append more to the hello
+        hello.discard_all()?;
+        (greeting, ProtocolVersion::new(major, minor))
+    };
+    debug!("Got hello {} {}", greeting.name(), server_version);
+    let version =
+        ProtocolVersion::negotiate(server_version).ok_or(ConnectionError::IncompatibleVersion {
+            major: server_version.major,
+            minor: server_version.minor,
+        })?;
+    info!("Speaking {} protocol {}", greeting.name(), version);
+
+    let name = greeting.name();
+    stream.write_u32(name.len() as u32 + 2 + 2 + 4 + device_name.len() as u32)?;
+    stream.write_all(name.as_bytes())?;
+    stream.write_u16(version.major)?;
+    stream.write_u16(version.minor)?;
+    stream.write_str(device_name)?;
+
+    actor.connected();
+    let ret = run(
+        PacketStream::with_version(stream, version),
+        screen_size,
+        actor,
+        wd,
+    );
+    actor.disconnected();
+    ret
+}
+
+/**
+ * Scales the screen coordinates to the absolute range [0, 0x7fff] of the HID mouse.
+ */
+fn to_absolute(x: u16, y: u16, screen_size: (u16, u16)) -> (u16, u16) {
+    let abs_x = ((x as f32) * (0x7fff as f32 / (screen_size.0 as f32))).ceil() as u16;
+    let abs_y = ((y as f32) * (0x7fff as f32 / (screen_size.1 as f32))).ceil() as u16;
+    (abs_x, abs_y)
+}
+
+// Grabs the clipboard if the device published one, only while the screen is entered
+fn grab_clipboard<S: PacketReader + PacketWriter, A: Actuator>(
+    packet_stream: &mut PacketStream<S>,
+    actor: &mut A,
+    entered: Option<u32>,
+    outgoing: &mut Option<(u32, Vec<u8>)>,
+) -> Result<(), ConnectionError> {
+    if let Some(seq_num) = entered {
+        if let Some(text) = actor.take_clipboard() {
+            packet_stream.write(Packet::GrabClipboard { id: 0, seq_num })?;
+            *outgoing = Some((seq_num, text));
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Handles the packets until the server closes the connection, which returns `Ok`, or anything goes wrong. Keep
+ * alives are sent even if the server doesn't send any, and a server silent for too long is treated as gone.
+ */
+fn run<S: PacketReader + PacketWriter + ReadTimeout, A: Actuator, W: Watchdog>(
+    mut packet_stream: PacketStream<S>,
+    screen_size: (u16, u16),
+    actor: &mut A,
+    wd: &mut W,
+) -> Result<(), ConnectionError> {
+    let mut keep_alive = KEEP_ALIVE_INTERVAL;
+    // Wake up regularly to send keep alives and feed the watchdog
+    packet_stream.set_read_timeout(Some(keep_alive)).ok();
+    let mut last_received = Instant::now();
+    let mut last_sent = Instant::now();
+
+    let mut clipboard = ClipboardAssembler::new(MAX_CLIPBOARD_SIZE);
+    // Sequence number of the current enter, the clipboard can only be grabbed when the screen is active
+    let mut entered: Option<u32> = None;
+    // The clipboard grabbed by this screen, sent to the server when the cursor leaves
+    let mut outgoing: Option<(u32, Vec<u8>)> = None;
+    loop {
+        let packet = match packet_stream.read() {
+            Ok(packet) => {
+                last_received = Instant::now();
+                packet
+            }
+            Err(PacketError::Idle) => {
+                // Servers older than 1.3 don't send anything when idle
+                if packet_stream.version().has_keep_alive()
+                    && last_received.elapsed() > keep_alive * KEEP_ALIVES_UNTIL_DEATH
+                {
+                    warn!(
+                        "No packet from the server for {:?}",
+                        last_received.elapsed()
+                    );
+                    return Err(ConnectionError::Timeout);
+                }
+                if last_sent.elapsed() >= keep_alive {
+                    packet_stream.write(Packet::KeepAlive)?;
+                    last_sent = Instant::now();
+                }
+                // The device clipboard doesn't wait for the server to send something
+                grab_clipboard(&mut packet_stream, actor, entered, &mut outgoing)?;
+                wd.feed();
+                continue;
+            }
+            Err(PacketError::Closed) => {
+                info!("Connection closed by the server");
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        match packet {
+            Packet::QueryInfo => {
+                packet_stream.write(Packet::DeviceInfo {
+                    x: 0,
+                    y: 0,
+                    w: screen_size.0,
+                    h: screen_size.1,
+                    _dummy: 0,
+                    mx: 0,
+                    my: 0,
+                })?;
+            }
+            Packet::KeepAlive => {
+                packet_stream.write(Packet::KeepAlive)?;
+                last_sent = Instant::now();
+                wd.feed();
+            }
+            Packet::MouseMoveAbs { x, y } => {
+                let (abs_x, abs_y) = to_absolute(x, y, screen_size);
+                actor.set_cursor_position(abs_x, abs_y);
+            }
+            Packet::MouseMoveRel { x, y } => {
+                actor.move_cursor(x, y);
+            }
+            Packet::KeyUp { id, mask, button } => {
+                actor.key_up(id, mask, button);
+            }
+            Packet::KeyDown { id, mask, button } => {
+                actor.key_down(id, mask, button);
+            }
+            Packet::KeyRepeat {
+                id,
+                mask,
+                button,
+                count,
+            } => {
+                actor.key_repeat(id, mask, button, count);
+            }
+            Packet::MouseDown { id } => {
+                actor.mouse_down(id);
+            }
+            Packet::MouseUp { id } => {
+                actor.mouse_up(id);
+            }
+            Packet::MouseWheel { x_delta, y_delta } => {
+                actor.mouse_wheel(x_delta, y_delta);
+            }
+            Packet::InfoAck => { //Ignore
+            }
+            Packet::ResetOptions => {
+                keep_alive = KEEP_ALIVE_INTERVAL;
+                packet_stream.set_read_timeout(Some(keep_alive)).ok();
+                actor.reset_options();
+            }
+            Packet::SetDeviceOptions { options } => {
+                info!("Set options: {:?}", options);
+                if let Some(heartbeat) = options.heartbeat.filter(|&h| h > 0) {
+                    keep_alive = Duration::from_millis(heartbeat as u64);
+                    // Still wake up often enough for the watchdog
+                    packet_stream
+                        .set_read_timeout(Some(keep_alive.min(KEEP_ALIVE_INTERVAL)))
+                        .ok();
+                }
+                actor.set_options(options);
+            }
+            Packet::CursorEnter {
+                x,
+                y,
+                seq_num,
+                mask,
+            } => {
+                entered = Some(seq_num);
+                let (abs_x, abs_y) = to_absolute(x, y, screen_size);
+                actor.enter(abs_x, abs_y, mask);
+            }
+            Packet::CursorLeave => {
+                entered = None;
+                if let Some((seq_num, text)) = outgoing.take() {
+                    debug!("Sending clipboard, size: {}", text.len());
+                    for packet in clipboard_chunks(0, seq_num, &text) {
+                        packet_stream.write(packet)?;
+                    }
+                }
+                actor.leave();
+            }
+            Packet::SetClipboard {
+                id,
+                seq_num,
+                mark,
+                data,
+            } => {
+                if let Some(data) = clipboard.feed(id, seq_num, mark, data) {
+                    actor.set_clipboard(data)
+                }
+            }
+            Packet::GrabClipboard { id, .. } => {
+                // Another screen owns the clipboard now
+                if id == 0 {
+                    outgoing = None;
+                }
+            }
+            Packet::ErrorUnknownDevice => return Err(ConnectionError::UnknownScreen),
+            Packet::ErrorBusy => return Err(ConnectionError::ScreenBusy),
+            Packet::ErrorIncompatible { major, minor } => {
+                return Err(ConnectionError::IncompatibleVersion { major, minor })
+            }
+            Packet::ErrorBadProtocol => return Err(ConnectionError::ProtocolViolation),
+            Packet::DeviceInfo { .. } | Packet::ClientNoOp => {
+                // Server only packets
+            }
+            Packet::Unknown(_) => {}
+        }
+
+        grab_clipboard(&mut packet_stream, actor, entered, &mut outgoing)?;
+
+        // The server may keep sending input without keep alives
+        if last_sent.elapsed() >= keep_alive {
+            packet_stream.write(Packet::KeepAlive)?;
+            last_sent = Instant::now();
+        }
+    }
+}
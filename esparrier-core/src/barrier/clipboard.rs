@@ -0,0 +1,280 @@
+use std::{
+    cmp::min,
+    io::Read,
+    sync::{Arc, Mutex},
+};
+
+use log::{debug, warn};
+
+use super::{Packet, PacketError, PacketReader, PacketWriter};
+
+/**
+ * Maximum number of clipboard bytes kept, the rest of the clipboard is read from the wire and discarded.
+ */
+pub const MAX_CLIPBOARD_SIZE: usize = 4096;
+
+// Size of the data chunks sent to the server, small enough to pass the packet size limit of other clients
+const CHUNK_SIZE: usize = 1024;
+
+// The clipboard being received, the server sends it in one mark 1 chunk with the total length, some mark 2 chunks
+// with the data, and an empty mark 3 chunk at the end.
+struct Transfer {
+    id: u8,
+    seq_num: u32,
+    expected: usize,
+    received: usize,
+    data: Vec<u8>,
+}
+
+/**
+ * Reassembles the DCLP chunks into the clipboard content.
+ */
+pub struct ClipboardAssembler {
+    max_size: usize,
+    transfer: Option<Transfer>,
+}
+
+impl ClipboardAssembler {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            transfer: None,
+        }
+    }
+
+    /**
+     * Feeds a DCLP chunk, returns the text in the clipboard once the last chunk is received.
+     * The data chunks may have been truncated by `PacketStream`, so they are only expected to add up to the
+     * total length if the clipboard fits in `max_size`.
+     */
+    pub fn feed(&mut self, id: u8, seq_num: u32, mark: u8, data: Vec<u8>) -> Option<Vec<u8>> {
+        match mark {
+            1 => {
+                if self.transfer.is_some() {
+                    warn!("Clipboard transfer restarted before the previous one completed");
+                }
+                let expected = std::str::from_utf8(&data)
+                    .ok()
+                    .and_then(|s| s.trim().parse().ok());
+                match expected {
+                    Some(expected) => {
+                        debug!("Clipboard {id}:{seq_num} started, size: {expected}");
+                        self.transfer = Some(Transfer {
+                            id,
+                            seq_num,
+                            expected,
+                            received: 0,
+                            data: Vec::with_capacity(min(expected, self.max_size)),
+                        });
+                    }
+                    None => {
+                        warn!("Invalid clipboard size: {:?}", data);
+                        self.transfer = None;
+                    }
+                }
+                None
+            }
+            2 => {
+                match self.transfer.as_mut() {
+                    Some(t) if t.id == id && t.seq_num == seq_num => {
+                        t.received += data.len();
+                        let room = self.max_size.saturating_sub(t.data.len());
+                        t.data.extend_from_slice(&data[..min(room, data.len())]);
+                    }
+                    _ => warn!("Unexpected clipboard chunk {id}:{seq_num}, discarding"),
+                }
+                None
+            }
+            3 => match self.transfer.take() {
+                Some(t) if t.id == id && t.seq_num == seq_num => {
+                    let truncated = t.data.len() >= self.max_size;
+                    if t.received != t.expected && !truncated {
+                        warn!(
+                            "Clipboard size mismatch, expected {}, received {}",
+                            t.expected, t.received
+                        );
+                        return None;
+                    }
+                    if truncated {
+                        warn!("Clipboard truncated to {} bytes", self.max_size);
+                    }
+                    parse_clipboard(&mut t.data.as_slice()).unwrap_or_else(|e| {
+                        warn!("Failed to parse clipboard: {:?}", e);
+                        None
+                    })
+                }
+                t => {
+                    warn!("Unexpected clipboard end {id}:{seq_num}");
+                    // Keep the transfer in progress if the end doesn't belong to it
+                    self.transfer = t;
+                    None
+                }
+            },
+            _ => {
+                warn!("Unexpected clipboard mark: {}", mark);
+                None
+            }
+        }
+    }
+}
+
+/**
+ * Handle to publish text as the clipboard of this screen, it can be cloned and used from any thread.
+ * The client grabs the clipboard when the screen is active, and sends the text to the server when the
+ * cursor leaves the screen.
+ */
+#[derive(Clone, Default)]
+pub struct ClipboardPublisher(Arc<Mutex<Option<Vec<u8>>>>);
+
+impl ClipboardPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Replaces the text waiting to be published, if any.
+     */
+    pub fn publish(&self, text: Vec<u8>) {
+        *self.0.lock().unwrap() = Some(text);
+    }
+
+    pub fn take(&self) -> Option<Vec<u8>> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ClipboardFormat {
+    Text = 0,
+    Html = 1,
+    Bitmap = 2,
+}
+
+/**
+ * Extracts the text from the serialized clipboard, the data may be truncated, in which case the text is cut
+ * where the data ends.
+ */
+pub fn parse_clipboard<T: PacketReader>(stream: &mut T) -> Result<Option<Vec<u8>>, PacketError> {
+    let mut ret = Vec::new();
+    let num_formats = stream.read_u32()?;
+
+    for _ in 0..num_formats {
+        let format = stream.read_u32()?;
+        let length = stream.read_u32()? as u64;
+
+        let format = match format {
+            0 => ClipboardFormat::Text,
+            1 => ClipboardFormat::Html,
+            2 => ClipboardFormat::Bitmap,
+            _ => Err(PacketError::FormatError)?,
+        };
+
+        if format == ClipboardFormat::Text {
+            stream.take(length).read_to_end(&mut ret)?;
+            // Only the text is needed, and the rest is probably truncated anyway
+            break;
+        } else {
+            stream.discard_exact(length as usize)?;
+        }
+    }
+    if ret.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(ret))
+    }
+}
+
+/**
+ * Serializes the text as a clipboard with only the plain text format.
+ */
+pub fn serialize_clipboard(text: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(text.len() + 12);
+    // Number of formats, followed by format, length and data of each format
+    data.write_u32(1).ok();
+    data.write_u32(ClipboardFormat::Text as u32).ok();
+    data.write_bytes(text).ok();
+    data
+}
+
+/**
+ * Splits the text into DCLP chunks in the same way as Barrier: the total length, the data chunks and an empty end.
+ */
+pub fn clipboard_chunks(id: u8, seq_num: u32, text: &[u8]) -> Vec<Packet> {
+    let data = serialize_clipboard(text);
+    let mut ret = vec![Packet::SetClipboard {
+        id,
+        seq_num,
+        mark: 1,
+        data: data.len().to_string().into_bytes(),
+    }];
+    ret.extend(data.chunks(CHUNK_SIZE).map(|chunk| Packet::SetClipboard {
+        id,
+        seq_num,
+        mark: 2,
+        data: chunk.to_vec(),
+    }));
+    ret.push(Packet::SetClipboard {
+        id,
+        seq_num,
+        mark: 3,
+        data: vec![],
+    });
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clipboard_chunks, serialize_clipboard as serialize, ClipboardAssembler};
+
+    #[test]
+    fn truncated() {
+        let data = serialize(b"0123456789");
+        let mut assembler = ClipboardAssembler::new(16);
+        assembler.feed(0, 1, 1, data.len().to_string().into_bytes());
+        for chunk in data.chunks(3) {
+            assert_eq!(assembler.feed(0, 1, 2, chunk.to_vec()), None);
+        }
+        assert_eq!(assembler.feed(0, 1, 3, vec![]), Some(b"0123".to_vec()));
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let data = serialize(b"hello");
+        let mut assembler = ClipboardAssembler::new(1024);
+        assembler.feed(0, 1, 1, (data.len() + 1).to_string().into_bytes());
+        assembler.feed(0, 1, 2, data);
+        assert_eq!(assembler.feed(0, 1, 3, vec![]), None);
+    }
+
+    #[test]
+    fn interleaved() {
+        let data = serialize(b"hello");
+        let mut assembler = ClipboardAssembler::new(1024);
+        assembler.feed(0, 1, 1, data.len().to_string().into_bytes());
+        // Chunk of another clipboard is ignored
+        assembler.feed(1, 1, 2, b"garbage".to_vec());
+        assembler.feed(0, 1, 2, data);
+        assert_eq!(assembler.feed(1, 1, 3, vec![]), None);
+        assert_eq!(assembler.feed(0, 1, 3, vec![]), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let text = vec![b'x'; 2500];
+        let mut assembler = ClipboardAssembler::new(4096);
+        let mut ret = None;
+        for packet in clipboard_chunks(0, 1, &text) {
+            if let crate::barrier::Packet::SetClipboard {
+                id,
+                seq_num,
+                mark,
+                data,
+            } = packet
+            {
+                ret = assembler.feed(id, seq_num, mark, data);
+            }
+        }
+        assert_eq!(ret, Some(text));
+    }
+}
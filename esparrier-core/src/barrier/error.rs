@@ -0,0 +1,62 @@
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PacketError {
+    #[error("io error")]
+    IoError(#[from] io::Error),
+    #[error("did not match format")]
+    FormatError,
+    #[error("not enough data")]
+    InsufficientDataError,
+    #[error("Packet too small")]
+    PacketTooSmall,
+    #[error("no packet before the read timeout")]
+    Idle,
+    #[error("connection closed")]
+    Closed,
+}
+
+#[derive(Error, Debug)]
+pub enum ConnectionError {
+    #[error("tcp connection failed")]
+    TcpError(#[from] io::Error),
+    #[error("invalid data received")]
+    ProtocolError(#[from] PacketError),
+    // The mbedtls error code
+    #[error("tls error -0x{:04X}", -.0)]
+    TlsError(i32),
+    // TLS failures that are not from mbedtls
+    #[error("tls setup failed: {0}")]
+    TlsSetupError(&'static str),
+    #[error("invalid server certificate fingerprint")]
+    InvalidFingerprint,
+    #[error("server certificate fingerprint mismatch")]
+    FingerprintMismatch,
+    #[error("server stopped responding")]
+    Timeout,
+    #[error("screen name is not in the server configuration")]
+    UnknownScreen,
+    #[error("screen name is already in use")]
+    ScreenBusy,
+    #[error("server requires protocol version {major}.{minor}")]
+    IncompatibleVersion { major: u16, minor: u16 },
+    #[error("server reported a protocol violation")]
+    ProtocolViolation,
+}
+
+impl ConnectionError {
+    /**
+     * Errors reported by the server that retrying won't fix until its configuration changes.
+     */
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            ConnectionError::UnknownScreen
+                | ConnectionError::ScreenBusy
+                | ConnectionError::IncompatibleVersion { .. }
+                | ConnectionError::ProtocolViolation
+        )
+    }
+}
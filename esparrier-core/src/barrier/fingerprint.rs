@@ -0,0 +1,77 @@
+use std::{fmt::Display, str::FromStr};
+
+use sha2::{Digest, Sha256};
+
+use super::ConnectionError;
+
+/**
+ * SHA-256 fingerprint of the server certificate, in the same format Barrier shows, e.g. `AB:CD:...:EF`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn of(der: &[u8]) -> Self {
+        Self(Sha256::digest(der).into())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = ConnectionError;
+
+    /**
+     * Accepts the colon separated form shown by Barrier, optionally prefixed with `SHA256:` or `v2:sha256:`
+     * as used in the Barrier/Input Leap fingerprint databases, separators and case are ignored.
+     */
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.trim().to_ascii_lowercase();
+        let hex = lower.trim_start_matches("v2:").trim_start_matches("sha256");
+        let digits: Vec<u8> = hex
+            .bytes()
+            .filter(|c| !matches!(c, b':' | b' ' | b'-'))
+            .collect();
+        if digits.len() != 64 {
+            return Err(ConnectionError::InvalidFingerprint);
+        }
+        let mut ret = [0; 32];
+        for (i, pair) in digits.chunks(2).enumerate() {
+            let pair =
+                std::str::from_utf8(pair).map_err(|_| ConnectionError::InvalidFingerprint)?;
+            ret[i] =
+                u8::from_str_radix(pair, 16).map_err(|_| ConnectionError::InvalidFingerprint)?;
+        }
+        Ok(Self(ret))
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02X}", b)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fingerprint;
+
+    #[test]
+    fn parse_and_display() {
+        let fp = Fingerprint::of(b"certificate");
+        let shown = fp.to_string();
+        assert_eq!(shown.len(), 32 * 3 - 1);
+        assert_eq!(shown.parse::<Fingerprint>().unwrap(), fp);
+        assert_eq!(
+            format!("v2:sha256:{}", shown.replace(':', "").to_lowercase())
+                .parse::<Fingerprint>()
+                .unwrap(),
+            fp
+        );
+        assert!("AB:CD".parse::<Fingerprint>().is_err());
+    }
+}
@@ -0,0 +1,805 @@
+//! In-process Barrier server and recording actuator, used to test the client loop on the host.
+
+use std::{
+    io::Write,
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use super::{
+    client,
+    clipboard::{self, serialize_clipboard, MAX_CLIPBOARD_SIZE},
+    Actuator, ClipboardPublisher, ConnectionError, DeviceOptions, Packet, PacketReader,
+    PacketStream, PacketWriter, ProtocolVersion,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    Connected,
+    Disconnected,
+    SetCursorPosition {
+        x: u16,
+        y: u16,
+    },
+    MoveCursor {
+        x: i16,
+        y: i16,
+    },
+    MouseDown {
+        button: i8,
+    },
+    MouseUp {
+        button: i8,
+    },
+    MouseWheel {
+        x: i16,
+        y: i16,
+    },
+    KeyDown {
+        key: u16,
+        mask: u16,
+        button: u16,
+    },
+    KeyRepeat {
+        key: u16,
+        mask: u16,
+        button: u16,
+        count: u16,
+    },
+    KeyUp {
+        key: u16,
+        mask: u16,
+        button: u16,
+    },
+    SetClipboard {
+        data: Vec<u8>,
+    },
+    SetOptions {
+        options: DeviceOptions,
+    },
+    ResetOptions,
+    Enter {
+        x: u16,
+        y: u16,
+        mask: u16,
+    },
+    Leave,
+    HidKeyDown {
+        key: u8,
+    },
+    HidKeyUp {
+        key: u8,
+    },
+}
+
+/**
+ * Records every call, the log is shared so it can be inspected after the actuator is moved into the client thread.
+ */
+pub struct RecordingActuator {
+    width: u16,
+    height: u16,
+    x: u16,
+    y: u16,
+    calls: Arc<Mutex<Vec<Call>>>,
+    clipboard: ClipboardPublisher,
+}
+
+impl RecordingActuator {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            x: 0,
+            y: 0,
+            calls: Arc::new(Mutex::new(vec![])),
+            clipboard: ClipboardPublisher::new(),
+        }
+    }
+
+    pub fn calls(&self) -> Arc<Mutex<Vec<Call>>> {
+        self.calls.clone()
+    }
+
+    pub fn get_clipboard_publisher(&self) -> ClipboardPublisher {
+        self.clipboard.clone()
+    }
+
+    fn record(&self, call: Call) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+impl Actuator for RecordingActuator {
+    fn connected(&mut self) {
+        self.record(Call::Connected)
+    }
+
+    fn disconnected(&mut self) {
+        self.record(Call::Disconnected)
+    }
+
+    fn get_screen_size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    fn get_cursor_position(&self) -> (u16, u16) {
+        (self.x, self.y)
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) {
+        self.x = x;
+        self.y = y;
+        self.record(Call::SetCursorPosition { x, y })
+    }
+
+    fn move_cursor(&mut self, x: i16, y: i16) {
+        self.record(Call::MoveCursor { x, y })
+    }
+
+    fn mouse_down(&mut self, button: i8) {
+        self.record(Call::MouseDown { button })
+    }
+
+    fn mouse_up(&mut self, button: i8) {
+        self.record(Call::MouseUp { button })
+    }
+
+    fn mouse_wheel(&mut self, x: i16, y: i16) {
+        self.record(Call::MouseWheel { x, y })
+    }
+
+    fn key_down(&mut self, key: u16, mask: u16, button: u16) {
+        self.record(Call::KeyDown { key, mask, button })
+    }
+
+    fn key_repeat(&mut self, key: u16, mask: u16, button: u16, count: u16) {
+        self.record(Call::KeyRepeat {
+            key,
+            mask,
+            button,
+            count,
+        })
+    }
+
+    fn key_up(&mut self, key: u16, mask: u16, button: u16) {
+        self.record(Call::KeyUp { key, mask, button })
+    }
+
+    fn set_clipboard(&mut self, data: Vec<u8>) {
+        self.record(Call::SetClipboard { data })
+    }
+
+    fn take_clipboard(&mut self) -> Option<Vec<u8>> {
+        self.clipboard.take()
+    }
+
+    fn set_options(&mut self, options: DeviceOptions) {
+        self.record(Call::SetOptions { options })
+    }
+
+    fn reset_options(&mut self) {
+        self.record(Call::ResetOptions)
+    }
+
+    fn enter(&mut self, x: u16, y: u16, mask: u16) {
+        self.record(Call::Enter { x, y, mask })
+    }
+
+    fn leave(&mut self) {
+        self.record(Call::Leave)
+    }
+
+    fn hid_key_down(&mut self, key: u8) {
+        self.record(Call::HidKeyDown { key })
+    }
+
+    fn hid_key_up(&mut self, key: u8) {
+        self.record(Call::HidKeyUp { key })
+    }
+}
+
+/**
+ * A Barrier server listening on a random loopback port, serving one client at a time.
+ */
+pub struct MockServer {
+    listener: TcpListener,
+}
+
+impl MockServer {
+    pub fn bind() -> Self {
+        Self {
+            listener: TcpListener::bind("127.0.0.1:0").unwrap(),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().unwrap().port()
+    }
+
+    /**
+     * Accepts a client and performs the hello handshake, greeting as `greeting` with the version `major.minor`, then
+     * speaks the version the client replied with.
+     */
+    pub fn accept_as(&self, greeting: &str, major: u16, minor: u16) -> MockConnection {
+        let (mut stream, _) = self.listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        stream.write_u32(greeting.len() as u32 + 2 + 2).unwrap();
+        stream.write_all(greeting.as_bytes()).unwrap();
+        stream.write_u16(major).unwrap();
+        stream.write_u16(minor).unwrap();
+
+        let _size = stream.read_packet_size().unwrap();
+        stream.read_str_lit(greeting).unwrap();
+        let major = stream.read_u16().unwrap();
+        let minor = stream.read_u16().unwrap();
+        let name = String::from_utf8(stream.read_bytes().unwrap()).unwrap();
+
+        MockConnection {
+            stream: PacketStream::with_version(stream, ProtocolVersion::new(major, minor)),
+            name,
+            version: (major, minor),
+        }
+    }
+}
+
+pub struct MockConnection {
+    stream: PacketStream<TcpStream>,
+    pub name: String,
+    pub version: (u16, u16),
+}
+
+impl MockConnection {
+    pub fn send(&mut self, packet: Packet) {
+        self.stream.write(packet).unwrap();
+    }
+
+    pub fn recv(&mut self) -> Packet {
+        self.stream.read().unwrap()
+    }
+
+    /**
+     * Sends the clipboard in the same way as the Barrier server: the total length, the data chunks and an empty end.
+     */
+    pub fn send_clipboard(&mut self, id: u8, seq_num: u32, text: &[u8], chunk_size: usize) {
+        let data = serialize_clipboard(text);
+        self.send(Packet::SetClipboard {
+            id,
+            seq_num,
+            mark: 1,
+            data: data.len().to_string().into_bytes(),
+        });
+        for chunk in data.chunks(chunk_size) {
+            self.send(Packet::SetClipboard {
+                id,
+                seq_num,
+                mark: 2,
+                data: chunk.to_vec(),
+            });
+        }
+        self.send(Packet::SetClipboard {
+            id,
+            seq_num,
+            mark: 3,
+            data: vec![],
+        });
+    }
+
+    /**
+     * Blocks until the client has processed everything sent so far, the client answers keep-alive in order.
+     */
+    pub fn sync(&mut self) {
+        self.send(Packet::KeepAlive);
+        // Clients of the versions before keep-alive answer with the heartbeat
+        let expected = if self.stream.version().has_keep_alive() {
+            Packet::KeepAlive
+        } else {
+            Packet::ClientNoOp
+        };
+        assert_eq!(self.recv(), expected);
+    }
+}
+
+pub type ClientHandle = thread::JoinHandle<Result<(), ConnectionError>>;
+
+/**
+ * Runs the client against the mock server in a separate thread, returns the connection and the shared call log.
+ */
+pub fn start_client(
+    name: &str,
+    width: u16,
+    height: u16,
+) -> (MockConnection, Arc<Mutex<Vec<Call>>>, ClientHandle) {
+    start_client_with(name, RecordingActuator::new(width, height))
+}
+
+pub fn start_client_with(
+    name: &str,
+    actor: RecordingActuator,
+) -> (MockConnection, Arc<Mutex<Vec<Call>>>, ClientHandle) {
+    start_client_on("Barrier", 1, 6, name, actor)
+}
+
+/**
+ * Same as `start_client_with`, against a server greeting with `greeting` and the version `major.minor`.
+ */
+pub fn start_client_on(
+    greeting: &str,
+    major: u16,
+    minor: u16,
+    name: &str,
+    mut actor: RecordingActuator,
+) -> (MockConnection, Arc<Mutex<Vec<Call>>>, ClientHandle) {
+    let server = MockServer::bind();
+    let port = server.port();
+    let name = name.to_owned();
+    let calls = actor.calls();
+    let handle = thread::spawn(move || {
+        let stream = client::connect("127.0.0.1", port)?;
+        client::start(stream, &name, &mut actor, &mut ())
+    });
+    (server.accept_as(greeting, major, minor), calls, handle)
+}
+
+#[test]
+fn handshake_and_query_info() {
+    let (mut conn, calls, _client) = start_client("TEST_SCREEN", 1024, 512);
+    assert_eq!(conn.name, "TEST_SCREEN");
+    assert_eq!(conn.version, (1, 6));
+
+    conn.send(Packet::QueryInfo);
+    assert_eq!(
+        conn.recv(),
+        Packet::DeviceInfo {
+            x: 0,
+            y: 0,
+            w: 1024,
+            h: 512,
+            _dummy: 0,
+            mx: 0,
+            my: 0,
+        }
+    );
+    conn.send(Packet::InfoAck);
+    let options = DeviceOptions {
+        heartbeat: Some(3000),
+        half_duplex_caps_lock: Some(true),
+        ..Default::default()
+    };
+    conn.send(Packet::SetDeviceOptions { options });
+    conn.sync();
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![Call::Connected, Call::SetOptions { options }]
+    );
+}
+
+#[test]
+fn input_events() {
+    let (mut conn, calls, _client) = start_client("TEST_SCREEN", 1024, 512);
+
+    // Entered in the middle of the screen with Caps Lock on
+    conn.send(Packet::CursorEnter {
+        x: 512,
+        y: 256,
+        seq_num: 1,
+        mask: 0x1000,
+    });
+    // Half of the screen is scaled to half of the absolute range [0, 0x7fff]
+    conn.send(Packet::MouseMoveAbs { x: 512, y: 256 });
+    conn.send(Packet::MouseMoveRel { x: -3, y: 7 });
+    conn.send(Packet::KeyDown {
+        id: 0x61,
+        mask: 0,
+        button: 38,
+    });
+    conn.send(Packet::KeyRepeat {
+        id: 0x61,
+        mask: 0,
+        button: 38,
+        count: 2,
+    });
+    conn.send(Packet::KeyUp {
+        id: 0x61,
+        mask: 0,
+        button: 38,
+    });
+    conn.send(Packet::MouseDown { id: 1 });
+    conn.send(Packet::MouseUp { id: 1 });
+    conn.send(Packet::MouseWheel {
+        x_delta: 0,
+        y_delta: 120,
+    });
+    conn.send(Packet::ResetOptions);
+    conn.send(Packet::CursorLeave);
+    conn.sync();
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            Call::Connected,
+            Call::Enter {
+                x: 16384,
+                y: 16384,
+                mask: 0x1000
+            },
+            Call::SetCursorPosition { x: 16384, y: 16384 },
+            Call::MoveCursor { x: -3, y: 7 },
+            Call::KeyDown {
+                key: 0x61,
+                mask: 0,
+                button: 38
+            },
+            Call::KeyRepeat {
+                key: 0x61,
+                mask: 0,
+                button: 38,
+                count: 2
+            },
+            Call::KeyUp {
+                key: 0x61,
+                mask: 0,
+                button: 38
+            },
+            Call::MouseDown { button: 1 },
+            Call::MouseUp { button: 1 },
+            Call::MouseWheel { x: 0, y: 120 },
+            Call::ResetOptions,
+            Call::Leave,
+        ]
+    );
+}
+
+#[test]
+fn clipboard_staging() {
+    let (mut conn, calls, _client) = start_client("TEST_SCREEN", 1024, 512);
+
+    conn.send(Packet::GrabClipboard { id: 0, seq_num: 1 });
+    conn.send_clipboard(0, 1, b"hello, world", 1024);
+    // A second clipboard, the client must go through the stages again
+    conn.send_clipboard(0, 2, b"second", 1024);
+    conn.sync();
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            Call::Connected,
+            Call::SetClipboard {
+                data: b"hello, world".to_vec()
+            },
+            Call::SetClipboard {
+                data: b"second".to_vec()
+            },
+        ]
+    );
+}
+
+#[test]
+fn clipboard_chunks() {
+    let (mut conn, calls, _client) = start_client("TEST_SCREEN", 1024, 512);
+
+    let text: Vec<u8> = (0..3000).map(|i| b'a' + (i % 26) as u8).collect();
+    conn.send_clipboard(0, 1, &text, 7);
+    conn.sync();
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![Call::Connected, Call::SetClipboard { data: text }]
+    );
+}
+
+#[test]
+fn clipboard_too_large() {
+    let (mut conn, calls, _client) = start_client("TEST_SCREEN", 1024, 512);
+
+    // Way over the packet size limit, in a single chunk
+    let text = vec![b'x'; 3 * MAX_CLIPBOARD_SIZE];
+    conn.send_clipboard(0, 1, &text, text.len() + 12);
+    conn.sync();
+
+    // The serialized clipboard has 12 bytes of headers before the text
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            Call::Connected,
+            Call::SetClipboard {
+                data: vec![b'x'; MAX_CLIPBOARD_SIZE - 12]
+            }
+        ]
+    );
+}
+
+#[test]
+fn publish_clipboard() {
+    let actor = RecordingActuator::new(1024, 512);
+    let publisher = actor.get_clipboard_publisher();
+    let (mut conn, _calls, _client) = start_client_with("TEST_SCREEN", actor);
+
+    // Not grabbed until the screen is entered
+    publisher.publish(b"hello from the device".to_vec());
+    conn.sync();
+    conn.send(Packet::CursorEnter {
+        x: 0,
+        y: 0,
+        seq_num: 7,
+        mask: 0,
+    });
+    assert_eq!(conn.recv(), Packet::GrabClipboard { id: 0, seq_num: 7 });
+
+    // The data is sent when leaving the screen, only once
+    conn.send(Packet::CursorLeave);
+    for packet in clipboard::clipboard_chunks(0, 7, b"hello from the device") {
+        assert_eq!(conn.recv(), packet);
+    }
+    conn.sync();
+}
+
+#[test]
+fn publish_clipboard_idle() {
+    let actor = RecordingActuator::new(1024, 512);
+    let publisher = actor.get_clipboard_publisher();
+    let (mut conn, _calls, _client) = start_client_with("TEST_SCREEN", actor);
+
+    conn.send(Packet::CursorEnter {
+        x: 0,
+        y: 0,
+        seq_num: 3,
+        mask: 0,
+    });
+    conn.sync();
+    publisher.publish(b"sent while idle".to_vec());
+    // The server sends nothing, the grab comes with the next keep alive of the client
+    let packet = match conn.recv() {
+        Packet::KeepAlive => conn.recv(),
+        packet => packet,
+    };
+    assert_eq!(packet, Packet::GrabClipboard { id: 0, seq_num: 3 });
+}
+
+#[test]
+fn publish_clipboard_lost() {
+    let actor = RecordingActuator::new(1024, 512);
+    let publisher = actor.get_clipboard_publisher();
+    let (mut conn, _calls, _client) = start_client_with("TEST_SCREEN", actor);
+
+    conn.send(Packet::CursorEnter {
+        x: 0,
+        y: 0,
+        seq_num: 1,
+        mask: 0,
+    });
+    conn.sync();
+    publisher.publish(b"never sent".to_vec());
+    // The grab is sent after the next packet
+    conn.send(Packet::ClientNoOp);
+    assert_eq!(conn.recv(), Packet::GrabClipboard { id: 0, seq_num: 1 });
+
+    // Another screen grabbed the clipboard in the meantime
+    conn.send(Packet::GrabClipboard { id: 0, seq_num: 2 });
+    conn.send(Packet::CursorLeave);
+    conn.sync();
+}
+
+#[test]
+fn keep_alive() {
+    let (mut conn, calls, _client) = start_client("TEST_SCREEN", 1024, 512);
+
+    for _ in 0..3 {
+        conn.sync();
+    }
+
+    assert_eq!(*calls.lock().unwrap(), vec![Call::Connected]);
+}
+
+#[test]
+fn server_closes() {
+    let (conn, calls, client) = start_client("TEST_SCREEN", 1024, 512);
+
+    drop(conn);
+
+    assert!(client.join().unwrap().is_ok());
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![Call::Connected, Call::Disconnected]
+    );
+}
+
+#[test]
+fn silent_server() {
+    let (mut conn, calls, client) = start_client("TEST_SCREEN", 1024, 512);
+
+    conn.send(Packet::SetDeviceOptions {
+        options: DeviceOptions {
+            heartbeat: Some(100),
+            ..Default::default()
+        },
+    });
+    // The client keeps the connection alive by itself
+    assert_eq!(conn.recv(), Packet::KeepAlive);
+
+    assert!(matches!(
+        client.join().unwrap(),
+        Err(ConnectionError::Timeout)
+    ));
+    assert_eq!(calls.lock().unwrap().last(), Some(&Call::Disconnected));
+}
+
+#[test]
+fn server_errors() {
+    let cases = [
+        (Packet::ErrorUnknownDevice, "UnknownScreen"),
+        (Packet::ErrorBusy, "ScreenBusy"),
+        (
+            Packet::ErrorIncompatible { major: 1, minor: 8 },
+            "IncompatibleVersion { major: 1, minor: 8 }",
+        ),
+        (Packet::ErrorBadProtocol, "ProtocolViolation"),
+    ];
+    for (packet, expected) in cases {
+        let (mut conn, calls, client) = start_client("TEST_SCREEN", 1024, 512);
+
+        conn.send(packet);
+
+        let err = client.join().unwrap().unwrap_err();
+        assert_eq!(format!("{:?}", err), expected);
+        assert!(err.is_rejected());
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![Call::Connected, Call::Disconnected]
+        );
+    }
+}
+
+#[test]
+fn synergy_greeting() {
+    let actor = RecordingActuator::new(1024, 512);
+    // A newer server, the client speaks the latest version it knows
+    let (mut conn, calls, _client) = start_client_on("Synergy", 1, 8, "TEST_SCREEN", actor);
+    assert_eq!(conn.name, "TEST_SCREEN");
+    assert_eq!(conn.version, (1, 6));
+
+    conn.sync();
+    assert_eq!(*calls.lock().unwrap(), vec![Call::Connected]);
+}
+
+#[test]
+fn legacy_clipboard() {
+    let actor = RecordingActuator::new(1024, 512);
+    let publisher = actor.get_clipboard_publisher();
+    let (mut conn, calls, _client) = start_client_on("Barrier", 1, 5, "TEST_SCREEN", actor);
+    assert_eq!(conn.version, (1, 5));
+
+    // Both sides send the whole clipboard in one packet
+    conn.send_clipboard(0, 1, b"from the server", 4);
+    conn.send(Packet::CursorEnter {
+        x: 0,
+        y: 0,
+        seq_num: 2,
+        mask: 0,
+    });
+    publisher.publish(b"from the device".to_vec());
+    conn.send(Packet::ClientNoOp);
+    assert_eq!(conn.recv(), Packet::GrabClipboard { id: 0, seq_num: 2 });
+    conn.send(Packet::CursorLeave);
+    for packet in clipboard::clipboard_chunks(0, 2, b"from the device") {
+        assert_eq!(conn.recv(), packet);
+    }
+    conn.sync();
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            Call::Connected,
+            Call::SetClipboard {
+                data: b"from the server".to_vec()
+            },
+            Call::Enter {
+                x: 0,
+                y: 0,
+                mask: 0
+            },
+            Call::Leave,
+        ]
+    );
+}
+
+#[test]
+fn legacy_key_buttons() {
+    let (mut conn, calls, _client) = start_client_on(
+        "Barrier",
+        1,
+        0,
+        "TEST_SCREEN",
+        RecordingActuator::new(1024, 512),
+    );
+    assert_eq!(conn.version, (1, 0));
+
+    // The mock server still writes the button, a client of 1.0 doesn't read it
+    conn.send(Packet::CursorEnter {
+        x: 0,
+        y: 0,
+        seq_num: 1,
+        mask: 0,
+    });
+    for packet in [
+        Packet::KeyDown {
+            id: 0x61,
+            mask: 0,
+            button: 0,
+        },
+        Packet::KeyDown {
+            id: 0x62,
+            mask: 0,
+            button: 0,
+        },
+        Packet::KeyUp {
+            id: 0x61,
+            mask: 0,
+            button: 0,
+        },
+        Packet::KeyUp {
+            id: 0x62,
+            mask: 0,
+            button: 0,
+        },
+    ] {
+        conn.send(packet);
+    }
+    conn.sync();
+
+    assert_eq!(
+        calls.lock().unwrap()[2..],
+        [
+            Call::KeyDown {
+                key: 0x61,
+                mask: 0,
+                button: 1
+            },
+            Call::KeyDown {
+                key: 0x62,
+                mask: 0,
+                button: 2
+            },
+            Call::KeyUp {
+                key: 0x61,
+                mask: 0,
+                button: 1
+            },
+            Call::KeyUp {
+                key: 0x62,
+                mask: 0,
+                button: 2
+            },
+        ]
+    );
+}
+
+#[test]
+fn incompatible_major_version() {
+    let server = MockServer::bind();
+    let port = server.port();
+    let client = thread::spawn(move || {
+        let stream = client::connect("127.0.0.1", port)?;
+        client::start(
+            stream,
+            "TEST_SCREEN",
+            &mut RecordingActuator::new(1024, 512),
+            &mut (),
+        )
+    });
+    let (mut stream, _) = server.listener.accept().unwrap();
+    stream.write_u32("Barrier".len() as u32 + 2 + 2).unwrap();
+    stream.write_all(b"Barrier").unwrap();
+    stream.write_u16(2).unwrap();
+    stream.write_u16(0).unwrap();
+
+    assert!(matches!(
+        client.join().unwrap(),
+        Err(ConnectionError::IncompatibleVersion { major: 2, minor: 0 })
+    ));
+}
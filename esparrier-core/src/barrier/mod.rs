@@ -6,16 +6,25 @@ mod packet_io;
 mod packet_stream;
 mod clipboard;
 mod take;
-#[cfg(feature = "paste")]
+mod fingerprint;
+mod watchdog;
+mod version;
+mod options;
+#[cfg(test)]
+mod mock_server;
 mod thread_act;
 
 pub use error::{ConnectionError, PacketError};
 pub use packet::Packet;
 pub use packet_io::{PacketReader, PacketWriter};
-pub use packet_stream::PacketStream;
+pub use packet_stream::{PacketStream, ReadTimeout};
 pub use actuator::Actuator;
-pub use client::start;
-#[cfg(feature = "paste")]
+pub use client::{connect, start};
+pub use clipboard::ClipboardPublisher;
+pub use fingerprint::Fingerprint;
+pub use watchdog::Watchdog;
+pub use version::{Greeting, ProtocolVersion};
+pub use options::{DeviceOptions, KeyOptions, Modifier};
 pub use thread_act::{ActMsg, ThreadedActuator};
 
 #[cfg(test)]
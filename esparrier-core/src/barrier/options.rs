@@ -0,0 +1,317 @@
+// Key ids of the modifiers, left and right
+const SHIFT_KEYS: (u16, u16) = (0xEFE1, 0xEFE2);
+const CONTROL_KEYS: (u16, u16) = (0xEFE3, 0xEFE4);
+const META_KEYS: (u16, u16) = (0xEFE7, 0xEFE8);
+const ALT_KEYS: (u16, u16) = (0xEFE9, 0xEFEA);
+const SUPER_KEYS: (u16, u16) = (0xEFEB, 0xEFEC);
+// ISO_Level3_Shift and kKeyAltGr (Mode_switch), there is no AltGr in HID, the right Alt is AltGr on the layouts
+// that have one
+const ALT_GR_KEYS: [u16; 2] = [0xFE03, 0xEF7E];
+
+const CAPS_LOCK_KEY: u16 = 0xEFE5;
+const NUM_LOCK_KEY: u16 = 0xEF7F;
+const SCROLL_LOCK_KEY: u16 = 0xEF14;
+
+/**
+ * The `KeyModifierID` of Barrier, the values of the modifier mapping options.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    // The modifier mapped to nothing, its keys are ignored
+    Disabled = 0,
+    Shift = 1,
+    Control = 2,
+    Alt = 3,
+    Meta = 4,
+    Super = 5,
+    AltGr = 6,
+}
+
+impl Modifier {
+    // The modifiers that can be mapped, in the order of `DeviceOptions::modifier_map`
+    pub const ALL: [Modifier; 6] = [
+        Modifier::Shift,
+        Modifier::Control,
+        Modifier::Alt,
+        Modifier::Meta,
+        Modifier::Super,
+        Modifier::AltGr,
+    ];
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(Modifier::Disabled),
+            1..=6 => Some(Self::ALL[id as usize - 1]),
+            _ => None,
+        }
+    }
+
+    /**
+     * The option setting what this modifier is mapped to.
+     */
+    pub fn option(&self) -> Option<&'static [u8; 4]> {
+        match self {
+            Modifier::Disabled => None,
+            Modifier::Shift => Some(b"MMFS"),
+            Modifier::Control => Some(b"MMFC"),
+            Modifier::Alt => Some(b"MMFA"),
+            Modifier::Meta => Some(b"MMFM"),
+            Modifier::Super => Some(b"MMFR"),
+            Modifier::AltGr => Some(b"MMFG"),
+        }
+    }
+
+    // The key ids, left and right, AltGr only has one
+    fn keys(&self) -> Option<(u16, u16)> {
+        match self {
+            Modifier::Disabled => None,
+            Modifier::Shift => Some(SHIFT_KEYS),
+            Modifier::Control => Some(CONTROL_KEYS),
+            Modifier::Alt => Some(ALT_KEYS),
+            Modifier::Meta => Some(META_KEYS),
+            Modifier::Super => Some(SUPER_KEYS),
+            Modifier::AltGr => Some((ALT_KEYS.1, ALT_KEYS.1)),
+        }
+    }
+
+    // The modifier of the key, and whether it's the right one
+    fn of_key(key: u16) -> Option<(Self, bool)> {
+        if ALT_GR_KEYS.contains(&key) {
+            return Some((Modifier::AltGr, true));
+        }
+        Self::ALL.into_iter().find_map(|m| match m.keys() {
+            Some((left, _)) if left == key && m != Modifier::AltGr => Some((m, false)),
+            Some((_, right)) if right == key && m != Modifier::AltGr => Some((m, true)),
+            _ => None,
+        })
+    }
+}
+
+/**
+ * The options of a DSOP packet, the ones not in the packet are `None` and stay unchanged.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceOptions {
+    // Keep alive interval in milliseconds
+    pub heartbeat: Option<u32>,
+    pub half_duplex_caps_lock: Option<bool>,
+    pub half_duplex_num_lock: Option<bool>,
+    pub half_duplex_scroll_lock: Option<bool>,
+    // What each modifier of `Modifier::ALL` is mapped to
+    pub modifier_map: [Option<Modifier>; 6],
+}
+
+impl DeviceOptions {
+    /**
+     * Sets an option from the DSOP packet, returns false for the unknown or invalid ones.
+     */
+    pub fn set(&mut self, option: &[u8; 4], value: u32) -> bool {
+        match option {
+            b"HBRT" => self.heartbeat = Some(value),
+            b"HDCL" => self.half_duplex_caps_lock = Some(value != 0),
+            b"HDNL" => self.half_duplex_num_lock = Some(value != 0),
+            b"HDSL" => self.half_duplex_scroll_lock = Some(value != 0),
+            _ => {
+                let Some(index) = Modifier::ALL
+                    .iter()
+                    .position(|m| m.option() == Some(option))
+                else {
+                    return false;
+                };
+                let Some(target) = Modifier::from_id(value) else {
+                    return false;
+                };
+                self.modifier_map[index] = Some(target);
+            }
+        }
+        true
+    }
+
+    /**
+     * The options set, as the DSOP packet carries them.
+     */
+    pub fn items(&self) -> Vec<([u8; 4], u32)> {
+        let mut ret = vec![];
+        if let Some(heartbeat) = self.heartbeat {
+            ret.push((*b"HBRT", heartbeat));
+        }
+        for (option, value) in [
+            (b"HDCL", self.half_duplex_caps_lock),
+            (b"HDNL", self.half_duplex_num_lock),
+            (b"HDSL", self.half_duplex_scroll_lock),
+        ] {
+            if let Some(value) = value {
+                ret.push((*option, value as u32));
+            }
+        }
+        for (modifier, target) in Modifier::ALL.iter().zip(self.modifier_map) {
+            if let (Some(option), Some(target)) = (modifier.option(), target) {
+                ret.push((*option, target as u32));
+            }
+        }
+        ret
+    }
+}
+
+/**
+ * The options affecting the key translation, accumulated from the DSOP packets until the next CROP.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyOptions {
+    modifier_map: [Modifier; 6],
+    half_duplex_caps_lock: bool,
+    half_duplex_num_lock: bool,
+    half_duplex_scroll_lock: bool,
+}
+
+impl Default for KeyOptions {
+    fn default() -> Self {
+        Self {
+            modifier_map: Modifier::ALL,
+            half_duplex_caps_lock: false,
+            half_duplex_num_lock: false,
+            half_duplex_scroll_lock: false,
+        }
+    }
+}
+
+impl KeyOptions {
+    pub fn apply(&mut self, options: &DeviceOptions) {
+        for (target, option) in self.modifier_map.iter_mut().zip(options.modifier_map) {
+            if let Some(option) = option {
+                *target = option;
+            }
+        }
+        if let Some(value) = options.half_duplex_caps_lock {
+            self.half_duplex_caps_lock = value;
+        }
+        if let Some(value) = options.half_duplex_num_lock {
+            self.half_duplex_num_lock = value;
+        }
+        if let Some(value) = options.half_duplex_scroll_lock {
+            self.half_duplex_scroll_lock = value;
+        }
+    }
+
+    /**
+     * The key to press for `key`, remapped if it's a modifier, `None` if the modifier is disabled.
+     */
+    pub fn map_key(&self, key: u16) -> Option<u16> {
+        let Some((modifier, right)) = Modifier::of_key(key) else {
+            return Some(key);
+        };
+        let target = self.modifier_map[modifier as usize - 1];
+        let (left_key, right_key) = target.keys()?;
+        Some(if right { right_key } else { left_key })
+    }
+
+    /**
+     * The server sends a key down when the lock turns on and a key up when it turns off, each has to be a full
+     * press and release.
+     */
+    pub fn is_half_duplex(&self, key: u16) -> bool {
+        match key {
+            CAPS_LOCK_KEY => self.half_duplex_caps_lock,
+            NUM_LOCK_KEY => self.half_duplex_num_lock,
+            SCROLL_LOCK_KEY => self.half_duplex_scroll_lock,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let mut options = DeviceOptions::default();
+        assert!(options.set(b"HBRT", 3000));
+        assert!(options.set(b"HDCL", 1));
+        assert!(options.set(b"MMFC", 5));
+        assert!(options.set(b"MMFR", 2));
+        assert!(!options.set(b"MMFA", 7));
+        assert!(!options.set(b"SSCM", 1));
+        assert_eq!(
+            options,
+            DeviceOptions {
+                heartbeat: Some(3000),
+                half_duplex_caps_lock: Some(true),
+                modifier_map: [
+                    None,
+                    Some(Modifier::Super),
+                    None,
+                    None,
+                    Some(Modifier::Control),
+                    None
+                ],
+                ..Default::default()
+            }
+        );
+        let mut decoded = DeviceOptions::default();
+        for (option, value) in options.items() {
+            decoded.set(&option, value);
+        }
+        assert_eq!(decoded, options);
+    }
+
+    #[test]
+    fn swap_control_and_super() {
+        let mut options = DeviceOptions::default();
+        options.set(b"MMFC", Modifier::Super as u32);
+        options.set(b"MMFR", Modifier::Control as u32);
+        let mut key_options = KeyOptions::default();
+        key_options.apply(&options);
+        assert_eq!(key_options.map_key(CONTROL_KEYS.0), Some(SUPER_KEYS.0));
+        assert_eq!(key_options.map_key(SUPER_KEYS.1), Some(CONTROL_KEYS.1));
+        assert_eq!(key_options.map_key(SHIFT_KEYS.0), Some(SHIFT_KEYS.0));
+        assert_eq!(key_options.map_key(0x61), Some(0x61));
+
+        // Later options only change what they set
+        let mut options = DeviceOptions::default();
+        options.set(b"MMFS", Modifier::Disabled as u32);
+        key_options.apply(&options);
+        assert_eq!(key_options.map_key(SHIFT_KEYS.1), None);
+        assert_eq!(key_options.map_key(CONTROL_KEYS.0), Some(SUPER_KEYS.0));
+
+        key_options = KeyOptions::default();
+        assert_eq!(key_options.map_key(CONTROL_KEYS.0), Some(CONTROL_KEYS.0));
+    }
+
+    fn check_alt_gr(key: u16) {
+        assert_eq!(Modifier::of_key(key), Some((Modifier::AltGr, true)));
+        let key_options = KeyOptions::default();
+        assert_eq!(key_options.map_key(key), Some(ALT_KEYS.1));
+        let mut options = DeviceOptions::default();
+        options.set(b"MMFG", Modifier::Control as u32);
+        let mut key_options = KeyOptions::default();
+        key_options.apply(&options);
+        assert_eq!(key_options.map_key(key), Some(CONTROL_KEYS.1));
+        // The right Alt is still Alt
+        assert_eq!(key_options.map_key(ALT_KEYS.1), Some(ALT_KEYS.1));
+    }
+
+    #[test]
+    fn alt_gr() {
+        check_alt_gr(0xFE03);
+    }
+
+    #[test]
+    fn alt_gr_mode_switch() {
+        check_alt_gr(0xEF7E);
+    }
+
+    #[test]
+    fn half_duplex() {
+        let mut key_options = KeyOptions::default();
+        assert!(!key_options.is_half_duplex(CAPS_LOCK_KEY));
+        let mut options = DeviceOptions::default();
+        options.set(b"HDCL", 1);
+        options.set(b"HDSL", 1);
+        key_options.apply(&options);
+        assert!(key_options.is_half_duplex(CAPS_LOCK_KEY));
+        assert!(!key_options.is_half_duplex(NUM_LOCK_KEY));
+        assert!(key_options.is_half_duplex(SCROLL_LOCK_KEY));
+        assert!(!key_options.is_half_duplex(0x61));
+    }
+}
@@ -0,0 +1,369 @@
+use std::io::Write;
+
+use super::{DeviceOptions, PacketError, PacketWriter};
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    QueryInfo,
+    DeviceInfo {
+        x: u16,
+        y: u16,
+        w: u16,
+        h: u16,
+        _dummy: u16,
+        mx: u16, // x position of the mouse on the secondary screen
+        my: u16, // y position of the mouse on the secondary screen
+    },
+    InfoAck,
+    KeepAlive,
+    ResetOptions,
+    ClientNoOp,
+    SetDeviceOptions {
+        options: DeviceOptions,
+    },
+    ErrorUnknownDevice,
+    ErrorBusy,
+    ErrorIncompatible {
+        major: u16,
+        minor: u16,
+    },
+    ErrorBadProtocol,
+    GrabClipboard {
+        id: u8,
+        seq_num: u32,
+    },
+    SetClipboard {
+        id: u8,
+        seq_num: u32,
+        // mark 1 is the total length string in ASCII
+        // mark 2 is the actual data and is split into chunks
+        // mark 3 is an empty chunk
+        mark: u8,
+        data: Vec<u8>,
+    },
+    CursorEnter {
+        x: u16,
+        y: u16,
+        seq_num: u32,
+        mask: u16,
+    },
+    MouseUp {
+        id: i8,
+    },
+    MouseDown {
+        id: i8,
+    },
+    KeyUp {
+        id: u16,
+        mask: u16,
+        button: u16,
+    },
+    KeyDown {
+        id: u16,
+        mask: u16,
+        button: u16,
+    },
+    KeyRepeat {
+        id: u16,
+        mask: u16,
+        button: u16,
+        count: u16,
+    },
+    MouseWheel {
+        x_delta: i16,
+        y_delta: i16,
+    },
+    CursorLeave,
+    MouseMoveAbs {
+        x: u16,
+        y: u16,
+    },
+    MouseMoveRel {
+        x: i16,
+        y: i16,
+    },
+    Unknown([u8; 4]),
+}
+
+impl Packet {
+    pub fn write_wire<W: Write + Send + Unpin>(self, mut out: W) -> Result<(), PacketError> {
+        match self {
+            Packet::QueryInfo => {
+                out.write_str("QINF")?;
+                Ok(())
+            }
+            Packet::DeviceInfo {
+                x,
+                y,
+                w,
+                h,
+                _dummy,
+                mx,
+                my,
+            } => {
+                out.write_u32(2 * 7 + 4)?;
+                out.write_all(b"DINF")?;
+                out.write_u16(x)?;
+                out.write_u16(y)?;
+                out.write_u16(w)?;
+                out.write_u16(h)?;
+                out.write_u16(_dummy)?;
+                out.write_u16(mx)?;
+                out.write_u16(my)?;
+                Ok(())
+            }
+            Packet::ClientNoOp => {
+                out.write_str("CNOP")?;
+                Ok(())
+            }
+            Packet::Unknown(code) => {
+                // Payload of unknown packets is discarded when reading, so only the code is left
+                out.write_u32(4)?;
+                out.write_all(&code)?;
+                Ok(())
+            }
+            Packet::InfoAck => {
+                out.write_str("CIAK")?;
+                Ok(())
+            }
+            Packet::KeepAlive => {
+                out.write_str("CALV")?;
+                Ok(())
+            }
+            Packet::ResetOptions => {
+                out.write_str("CROP")?;
+                Ok(())
+            }
+            Packet::SetDeviceOptions { options } => {
+                let items = options.items();
+                out.write_u32(4 + 4 + 8 * items.len() as u32)?;
+                out.write_all(b"DSOP")?;
+                // Number of items, each option is a pair of name and value
+                out.write_u32(2 * items.len() as u32)?;
+                for (option, value) in items {
+                    out.write_all(&option)?;
+                    out.write_u32(value)?;
+                }
+                Ok(())
+            }
+            Packet::ErrorUnknownDevice => {
+                out.write_str("EUNK")?;
+                Ok(())
+            }
+            Packet::ErrorBusy => {
+                out.write_str("EBSY")?;
+                Ok(())
+            }
+            Packet::ErrorIncompatible { major, minor } => {
+                out.write_u32(4 + 2 + 2)?;
+                out.write_all(b"EICV")?;
+                out.write_u16(major)?;
+                out.write_u16(minor)?;
+                Ok(())
+            }
+            Packet::ErrorBadProtocol => {
+                out.write_str("EBAD")?;
+                Ok(())
+            }
+            Packet::GrabClipboard { id, seq_num } => {
+                out.write_u32(4 + 1 + 4)?;
+                out.write_all(b"CCLP")?;
+                out.write_u8(id)?;
+                out.write_u32(seq_num)?;
+                Ok(())
+            }
+            Packet::SetClipboard {
+                id,
+                seq_num,
+                mark,
+                data,
+            } => {
+                out.write_u32(4 + 1 + 4 + 1 + 4 + data.len() as u32)?;
+                out.write_all(b"DCLP")?;
+                out.write_u8(id)?;
+                out.write_u32(seq_num)?;
+                out.write_u8(mark)?;
+                out.write_bytes(&data)?;
+                Ok(())
+            }
+            Packet::CursorEnter {
+                x,
+                y,
+                seq_num,
+                mask,
+            } => {
+                out.write_u32(4 + 2 + 2 + 4 + 2)?;
+                out.write_all(b"CINN")?;
+                out.write_u16(x)?;
+                out.write_u16(y)?;
+                out.write_u32(seq_num)?;
+                out.write_u16(mask)?;
+                Ok(())
+            }
+            Packet::CursorLeave => {
+                out.write_str("COUT")?;
+                Ok(())
+            }
+            Packet::MouseUp { id } => {
+                out.write_u32(4 + 1)?;
+                out.write_all(b"DMUP")?;
+                out.write_i8(id)?;
+                Ok(())
+            }
+            Packet::MouseDown { id } => {
+                out.write_u32(4 + 1)?;
+                out.write_all(b"DMDN")?;
+                out.write_i8(id)?;
+                Ok(())
+            }
+            Packet::KeyUp { id, mask, button } => {
+                out.write_u32(4 + 2 + 2 + 2)?;
+                out.write_all(b"DKUP")?;
+                out.write_u16(id)?;
+                out.write_u16(mask)?;
+                out.write_u16(button)?;
+                Ok(())
+            }
+            Packet::KeyDown { id, mask, button } => {
+                out.write_u32(4 + 2 + 2 + 2)?;
+                out.write_all(b"DKDN")?;
+                out.write_u16(id)?;
+                out.write_u16(mask)?;
+                out.write_u16(button)?;
+                Ok(())
+            }
+            Packet::KeyRepeat {
+                id,
+                mask,
+                button,
+                count,
+            } => {
+                out.write_u32(4 + 2 + 2 + 2 + 2)?;
+                out.write_all(b"DKRP")?;
+                out.write_u16(id)?;
+                out.write_u16(mask)?;
+                out.write_u16(count)?;
+                out.write_u16(button)?;
+                Ok(())
+            }
+            Packet::MouseWheel { x_delta, y_delta } => {
+                out.write_u32(4 + 2 + 2)?;
+                out.write_all(b"DMWM")?;
+                out.write_i16(x_delta)?;
+                out.write_i16(y_delta)?;
+                Ok(())
+            }
+            Packet::MouseMoveAbs { x, y } => {
+                out.write_u32(4 + 2 + 2)?;
+                out.write_all(b"DMMV")?;
+                out.write_u16(x)?;
+                out.write_u16(y)?;
+                Ok(())
+            }
+            Packet::MouseMoveRel { x, y } => {
+                out.write_u32(4 + 2 + 2)?;
+                out.write_all(b"DMRM")?;
+                out.write_i16(x)?;
+                out.write_i16(y)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::Packet;
+    use crate::barrier::{DeviceOptions, Modifier, PacketStream};
+
+    fn round_trip(packet: Packet) {
+        let mut buf = vec![];
+        packet.clone().write_wire(&mut buf).unwrap();
+        let mut stream = PacketStream::new(Cursor::new(buf));
+        assert_eq!(stream.read().unwrap(), packet);
+    }
+
+    #[test]
+    fn round_trip_all_packets() {
+        round_trip(Packet::QueryInfo);
+        round_trip(Packet::DeviceInfo {
+            x: 1,
+            y: 2,
+            w: 1920,
+            h: 1080,
+            _dummy: 0,
+            mx: 100,
+            my: 200,
+        });
+        round_trip(Packet::InfoAck);
+        round_trip(Packet::KeepAlive);
+        round_trip(Packet::ResetOptions);
+        round_trip(Packet::ClientNoOp);
+        round_trip(Packet::SetDeviceOptions {
+            options: DeviceOptions {
+                heartbeat: Some(3000),
+                half_duplex_num_lock: Some(true),
+                modifier_map: [None, None, Some(Modifier::Meta), None, None, None],
+                ..Default::default()
+            },
+        });
+        round_trip(Packet::ErrorUnknownDevice);
+        round_trip(Packet::ErrorBusy);
+        round_trip(Packet::ErrorIncompatible { major: 1, minor: 6 });
+        round_trip(Packet::ErrorBadProtocol);
+        round_trip(Packet::GrabClipboard { id: 1, seq_num: 42 });
+        round_trip(Packet::SetClipboard {
+            id: 0,
+            seq_num: 42,
+            mark: 1,
+            data: b"5".to_vec(),
+        });
+        round_trip(Packet::SetClipboard {
+            id: 0,
+            seq_num: 42,
+            mark: 2,
+            data: b"hello".to_vec(),
+        });
+        round_trip(Packet::SetClipboard {
+            id: 0,
+            seq_num: 42,
+            mark: 3,
+            data: vec![],
+        });
+        round_trip(Packet::CursorEnter {
+            x: 10,
+            y: 20,
+            seq_num: 7,
+            mask: 0x1000,
+        });
+        round_trip(Packet::CursorLeave);
+        round_trip(Packet::MouseUp { id: 1 });
+        round_trip(Packet::MouseDown { id: 3 });
+        round_trip(Packet::KeyUp {
+            id: 0x61,
+            mask: 0x0002,
+            button: 38,
+        });
+        round_trip(Packet::KeyDown {
+            id: 0x61,
+            mask: 0x0002,
+            button: 38,
+        });
+        round_trip(Packet::KeyRepeat {
+            id: 0x61,
+            mask: 0x0002,
+            button: 38,
+            count: 3,
+        });
+        round_trip(Packet::MouseWheel {
+            x_delta: -120,
+            y_delta: 240,
+        });
+        round_trip(Packet::MouseMoveAbs { x: 640, y: 480 });
+        round_trip(Packet::MouseMoveRel { x: -5, y: 300 });
+        round_trip(Packet::Unknown(*b"XXXX"));
+    }
+}
@@ -41,8 +41,7 @@ pub trait PacketReader: Read + Send + Unpin {
 
         let len = self.read_u32()?;
 
-        let mut chunk =
-            self.take(u64::try_from(len).map_err(|_| PacketError::InsufficientDataError)?);
+        let mut chunk = self.take(u64::from(len));
         chunk.read_to_end(&mut buf)?;
 
         Ok(buf)
@@ -110,6 +109,24 @@ pub trait PacketWriter: Write + Send + Unpin {
         Ok(())
     }
 
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), PacketError> {
+        self.write_u32(data.len() as u32)?;
+        self.write_all(data)?;
+        Ok(())
+    }
+
+    fn write_i8(&mut self, data: i8) -> Result<(), PacketError> {
+        Ok(self.write_all(&data.to_be_bytes())?)
+    }
+
+    fn write_u8(&mut self, data: u8) -> Result<(), PacketError> {
+        Ok(self.write_all(&[data])?)
+    }
+
+    fn write_i16(&mut self, data: i16) -> Result<(), PacketError> {
+        Ok(self.write_all(&data.to_be_bytes())?)
+    }
+
     fn write_u16(&mut self, data: u16) -> Result<(), PacketError> {
         Ok(self.write_all(&data.to_be_bytes())?)
     }
@@ -0,0 +1,470 @@
+use std::{
+    cmp::min,
+    collections::VecDeque,
+    io::{ErrorKind, Read},
+    net::TcpStream,
+};
+
+use log::{debug, warn};
+
+use super::{
+    clipboard::MAX_CLIPBOARD_SIZE, DeviceOptions, Packet, PacketError, PacketReader, PacketWriter,
+    ProtocolVersion,
+};
+
+// Servers older than 1.1 don't send the button of the key, it's made up to pair the key up with its key down
+const MAX_LEGACY_BUTTONS: usize = 128;
+
+#[derive(Default)]
+struct LegacyButtons {
+    // The key ids held down, the button is the index plus 1, 0 is a free slot
+    held: Vec<u16>,
+}
+
+impl LegacyButtons {
+    fn find(&self, id: u16) -> Option<usize> {
+        self.held.iter().position(|held| *held == id)
+    }
+
+    fn down(&mut self, id: u16) -> u16 {
+        let slot = match (self.find(id), self.find(0)) {
+            (Some(slot), _) | (None, Some(slot)) => slot,
+            (None, None) if self.held.len() < MAX_LEGACY_BUTTONS => {
+                self.held.push(0);
+                self.held.len() - 1
+            }
+            // Too many keys held down
+            (None, None) => return 0,
+        };
+        self.held[slot] = id;
+        slot as u16 + 1
+    }
+
+    fn repeat(&self, id: u16) -> u16 {
+        self.find(id)
+            .map(|slot| slot as u16 + 1)
+            .unwrap_or_default()
+    }
+
+    fn up(&mut self, id: u16) -> u16 {
+        match self.find(id) {
+            Some(slot) => {
+                self.held[slot] = 0;
+                slot as u16 + 1
+            }
+            None => 0,
+        }
+    }
+}
+
+/**
+ * Reads and writes the packets in the negotiated protocol version. The packets of older versions are translated
+ * from and to the ones of the latest version, so the client doesn't need to care.
+ */
+pub struct PacketStream<S: PacketReader + PacketWriter> {
+    stream: S,
+    version: ProtocolVersion,
+    // Packets decoded from one packet of an older version, returned before reading the next one
+    pending: VecDeque<Packet>,
+    // The clipboard chunks to send in one packet to a server older than 1.6
+    outgoing_clipboard: Option<Vec<u8>>,
+    legacy_buttons: LegacyButtons,
+}
+
+impl<S: PacketReader + PacketWriter> PacketStream<S> {
+    pub fn new(stream: S) -> Self {
+        Self::with_version(stream, ProtocolVersion::LATEST)
+    }
+
+    pub fn with_version(stream: S, version: ProtocolVersion) -> Self {
+        Self {
+            stream,
+            version,
+            pending: VecDeque::new(),
+            outgoing_clipboard: None,
+            legacy_buttons: LegacyButtons::default(),
+        }
+    }
+
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /**
+     * Reads the next packet, `PacketError::Idle` if the read timeout expires before the packet starts, and
+     * `PacketError::Closed` if the connection is closed between packets. A timeout in the middle of a packet leaves
+     * the stream out of sync, so it is reported as an IO error.
+     */
+    pub fn read(&mut self) -> Result<Packet, PacketError> {
+        if let Some(packet) = self.pending.pop_front() {
+            return Ok(packet);
+        }
+        let version = self.version;
+        let size = self.read_size()?;
+        if size < 4 {
+            let mut vec = Vec::new();
+            self.stream.read_to_end(&mut vec)?;
+            return Err(PacketError::PacketTooSmall);
+        }
+        let mut chunk = super::take::Take::new(&mut self.stream, size as u64);
+        let code: [u8; 4] = chunk.read_bytes_fixed()?;
+        // Clipboard chunks can be much larger, their data is capped below
+        if size > 2048 && &code != b"DCLP" {
+            warn!("Packet too large, discarding {} bytes", size);
+            chunk.discard_all()?;
+            return Ok(Packet::Unknown(code));
+        }
+
+        let packet = match code.as_ref() {
+            b"QINF" => Packet::QueryInfo,
+            b"DINF" => {
+                let x = chunk.read_u16()?;
+                let y = chunk.read_u16()?;
+                let w = chunk.read_u16()?;
+                let h = chunk.read_u16()?;
+                let _dummy = chunk.read_u16()?;
+                let mx = chunk.read_u16()?;
+                let my = chunk.read_u16()?;
+                Packet::DeviceInfo {
+                    x,
+                    y,
+                    w,
+                    h,
+                    _dummy,
+                    mx,
+                    my,
+                }
+            }
+            b"CIAK" => Packet::InfoAck,
+            b"CALV" => Packet::KeepAlive,
+            b"CNOP" => Packet::ClientNoOp,
+            b"CROP" => Packet::ResetOptions,
+            b"DSOP" => {
+                let num_items = chunk.read_u32()?;
+                let num_opts = num_items / 2;
+                let mut options = DeviceOptions::default();
+                for _ in 0..num_opts {
+                    let opt: [u8; 4] = chunk.read_bytes_fixed()?;
+                    let val = chunk.read_u32()?;
+                    if !options.set(&opt, val) {
+                        debug!("Ignoring option {:?}: {}", opt, val);
+                    }
+                }
+                Packet::SetDeviceOptions { options }
+            }
+            b"EUNK" => Packet::ErrorUnknownDevice,
+            b"EBSY" => Packet::ErrorBusy,
+            b"EICV" => {
+                let major = chunk.read_u16()?;
+                let minor = chunk.read_u16()?;
+                Packet::ErrorIncompatible { major, minor }
+            }
+            b"EBAD" => Packet::ErrorBadProtocol,
+            b"DMMV" => {
+                let x = chunk.read_u16()?;
+                let y = chunk.read_u16()?;
+                Packet::MouseMoveAbs { x, y }
+            }
+            b"DMRM" => {
+                let x = chunk.read_i16()?;
+                let y = chunk.read_i16()?;
+                Packet::MouseMoveRel { x, y }
+            }
+            b"CINN" => {
+                let x = chunk.read_u16()?;
+                let y = chunk.read_u16()?;
+                let seq_num = chunk.read_u32()?;
+                let mask = chunk.read_u16()?;
+                Packet::CursorEnter {
+                    x,
+                    y,
+                    seq_num,
+                    mask,
+                }
+            }
+            b"COUT" => Packet::CursorLeave,
+            b"CCLP" => {
+                let id = chunk.read_u8()?;
+                let seq_num = chunk.read_u32()?;
+                Packet::GrabClipboard { id, seq_num }
+            }
+            b"DCLP" if !version.has_clipboard_chunks() => {
+                // The whole clipboard in one packet, split it as the newer servers do
+                let id = chunk.read_u8()?;
+                let seq_num = chunk.read_u32()?;
+                let len = chunk.read_u32()? as u64;
+                let mut data = vec![];
+                (&mut chunk)
+                    .take(min(len, MAX_CLIPBOARD_SIZE as u64))
+                    .read_to_end(&mut data)?;
+                chunk.discard_all()?;
+                self.pending.push_back(Packet::SetClipboard {
+                    id,
+                    seq_num,
+                    mark: 2,
+                    data,
+                });
+                self.pending.push_back(Packet::SetClipboard {
+                    id,
+                    seq_num,
+                    mark: 3,
+                    data: vec![],
+                });
+                Packet::SetClipboard {
+                    id,
+                    seq_num,
+                    mark: 1,
+                    data: len.to_string().into_bytes(),
+                }
+            }
+            b"DCLP" => {
+                let id = chunk.read_u8()?;
+                let seq_num = chunk.read_u32()?;
+                let mark = chunk.read_u8()?;
+                debug!("DCLP chunk, size: {}, mark: {}", size, mark);
+                let len = chunk.read_u32()? as u64;
+                let mut data = vec![];
+                (&mut chunk)
+                    .take(min(len, MAX_CLIPBOARD_SIZE as u64))
+                    .read_to_end(&mut data)?;
+                // Anything beyond the cap wouldn't be kept by the clipboard anyway
+                chunk.discard_all()?;
+                Packet::SetClipboard {
+                    id,
+                    seq_num,
+                    mark,
+                    data,
+                }
+            }
+            b"DMUP" => {
+                let id = chunk.read_i8()?;
+                Packet::MouseUp { id }
+            }
+            b"DMDN" => {
+                let id = chunk.read_i8()?;
+                Packet::MouseDown { id }
+            }
+            b"DKUP" => {
+                let id = chunk.read_u16()?;
+                let mask = chunk.read_u16()?;
+                let button = if version.has_key_button() {
+                    chunk.read_u16()?
+                } else {
+                    self.legacy_buttons.up(id)
+                };
+                Packet::KeyUp { id, mask, button }
+            }
+            b"DKDN" => {
+                let id = chunk.read_u16()?;
+                let mask = chunk.read_u16()?;
+                let button = if version.has_key_button() {
+                    chunk.read_u16()?
+                } else {
+                    self.legacy_buttons.down(id)
+                };
+                Packet::KeyDown { id, mask, button }
+            }
+            b"DKRP" => {
+                let id = chunk.read_u16()?;
+                let mask = chunk.read_u16()?;
+                let count = chunk.read_u16()?;
+                let button = if version.has_key_button() {
+                    chunk.read_u16()?
+                } else {
+                    self.legacy_buttons.repeat(id)
+                };
+                Packet::KeyRepeat {
+                    id,
+                    mask,
+                    button,
+                    count,
+                }
+            }
+            b"DMWM" => {
+                let (x_delta, y_delta) = if version.has_horizontal_wheel() {
+                    (chunk.read_i16()?, chunk.read_i16()?)
+                } else {
+                    (0, chunk.read_i16()?)
+                };
+                Packet::MouseWheel { x_delta, y_delta }
+            }
+            _ => Packet::Unknown(code),
+        };
+
+        // Discard the rest of the packet
+        while chunk.limit() > 0 {
+            warn!(
+                "Discarding rest of packet, code: {:?}, size: {}",
+                code,
+                chunk.limit()
+            );
+            chunk.discard_all()?;
+        }
+
+        Ok(packet)
+    }
+
+    fn read_size(&mut self) -> Result<u32, PacketError> {
+        let mut buf = [0; 4];
+        loop {
+            match self.stream.read(&mut buf[..1]) {
+                Ok(0) => return Err(PacketError::Closed),
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Err(PacketError::Idle)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.stream.read_exact(&mut buf[1..])?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    /**
+     * Writes the packet, only the ones sent by the client are translated for the older versions.
+     */
+    pub fn write(&mut self, packet: Packet) -> Result<(), PacketError> {
+        let packet = match packet {
+            // The server of an older version expects the client heartbeat instead
+            Packet::KeepAlive if !self.version.has_keep_alive() => Packet::ClientNoOp,
+            Packet::SetClipboard {
+                id,
+                seq_num,
+                mark,
+                data,
+            } if !self.version.has_clipboard_chunks() => {
+                match mark {
+                    1 => self.outgoing_clipboard = Some(vec![]),
+                    2 => {
+                        if let Some(buf) = self.outgoing_clipboard.as_mut() {
+                            buf.extend_from_slice(&data);
+                        }
+                    }
+                    _ => {
+                        let data = self.outgoing_clipboard.take().unwrap_or_default();
+                        self.stream.write_u32(4 + 1 + 4 + 4 + data.len() as u32)?;
+                        self.stream.write_all(b"DCLP")?;
+                        self.stream.write_u8(id)?;
+                        self.stream.write_u32(seq_num)?;
+                        self.stream.write_bytes(&data)?;
+                    }
+                }
+                return Ok(());
+            }
+            packet => packet,
+        };
+        packet.write_wire(&mut self.stream)?;
+        Ok(())
+    }
+}
+
+pub trait ReadTimeout {
+    fn set_read_timeout(
+        &mut self,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<(), std::io::Error>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(
+        &mut self,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<(), std::io::Error> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl<S: PacketReader + PacketWriter + ReadTimeout> ReadTimeout for PacketStream<S> {
+    fn set_read_timeout(
+        &mut self,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<(), std::io::Error> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::PacketStream;
+    use crate::barrier::{Packet, PacketWriter, ProtocolVersion};
+
+    fn packet(code: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut ret = vec![];
+        ret.write_u32(code.len() as u32 + payload.len() as u32)
+            .unwrap();
+        ret.extend_from_slice(code);
+        ret.extend_from_slice(payload);
+        ret
+    }
+
+    #[test]
+    fn legacy_input() {
+        let mut buf = packet(b"DKDN", &[0, 0x61, 0, 2]);
+        buf.extend(packet(b"DKRP", &[0, 0x61, 0, 2, 0, 3]));
+        buf.extend(packet(b"DKDN", &[0, 0x62, 0, 2]));
+        buf.extend(packet(b"DKUP", &[0, 0x61, 0, 2]));
+        buf.extend(packet(b"DKDN", &[0, 0x63, 0, 2]));
+        buf.extend(packet(b"DMWM", &[0xff, 0x88]));
+        let mut stream = PacketStream::with_version(Cursor::new(buf), ProtocolVersion::new(1, 0));
+        assert_eq!(
+            stream.read().unwrap(),
+            Packet::KeyDown {
+                id: 0x61,
+                mask: 2,
+                button: 1
+            }
+        );
+        assert_eq!(
+            stream.read().unwrap(),
+            Packet::KeyRepeat {
+                id: 0x61,
+                mask: 2,
+                button: 1,
+                count: 3
+            }
+        );
+        // Every key held down gets its own button, and the free ones are reused
+        assert_eq!(
+            stream.read().unwrap(),
+            Packet::KeyDown {
+                id: 0x62,
+                mask: 2,
+                button: 2
+            }
+        );
+        assert_eq!(
+            stream.read().unwrap(),
+            Packet::KeyUp {
+                id: 0x61,
+                mask: 2,
+                button: 1
+            }
+        );
+        assert_eq!(
+            stream.read().unwrap(),
+            Packet::KeyDown {
+                id: 0x63,
+                mask: 2,
+                button: 1
+            }
+        );
+        assert_eq!(
+            stream.read().unwrap(),
+            Packet::MouseWheel {
+                x_delta: 0,
+                y_delta: -120
+            }
+        );
+    }
+
+    #[test]
+    fn legacy_heartbeat() {
+        let mut stream =
+            PacketStream::with_version(Cursor::new(vec![]), ProtocolVersion::new(1, 2));
+        stream.write(Packet::KeepAlive).unwrap();
+        assert_eq!(stream.stream.into_inner(), packet(b"CNOP", &[]));
+    }
+}
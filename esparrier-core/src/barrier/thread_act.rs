@@ -1,10 +1,14 @@
 use std::{
     marker::PhantomData,
-    sync::mpsc::{sync_channel, SyncSender},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, SyncSender},
+        Arc,
+    },
     thread,
 };
 
-use super::Actuator;
+use super::{Actuator, ClipboardPublisher, DeviceOptions};
 
 pub enum ActMsg {
     Connected,
@@ -47,10 +51,14 @@ pub enum ActMsg {
         data: Vec<u8>,
     },
     SetOptions {
-        heartbeat: u32,
+        options: DeviceOptions,
     },
     ResetOptions,
-    Enter,
+    Enter {
+        x: u16,
+        y: u16,
+        mask: u16,
+    },
     Leave,
     HidKeyDown {
         key: u8,
@@ -66,6 +74,8 @@ pub struct ThreadedActuator<T> {
     cursor_x: u16,
     cursor_y: u16,
     tx: SyncSender<ActMsg>,
+    clipboard: ClipboardPublisher,
+    input_counter: Arc<AtomicUsize>,
     _t: PhantomData<T>,
 }
 
@@ -95,9 +105,9 @@ impl<T: Actuator + Send + 'static> ThreadedActuator<T> {
                         } => actuator.key_repeat(key, mask, button, count),
                         ActMsg::KeyUp { key, mask, button } => actuator.key_up(key, mask, button),
                         ActMsg::SetClipboard { data } => actuator.set_clipboard(data),
-                        ActMsg::SetOptions { heartbeat } => actuator.set_options(heartbeat),
+                        ActMsg::SetOptions { options } => actuator.set_options(options),
                         ActMsg::ResetOptions => actuator.reset_options(),
-                        ActMsg::Enter => actuator.enter(),
+                        ActMsg::Enter { x, y, mask } => actuator.enter(x, y, mask),
                         ActMsg::Leave => actuator.leave(),
                         ActMsg::HidKeyDown { key } => actuator.hid_key_down(key),
                         ActMsg::HidKeyUp { key } => actuator.hid_key_up(key),
@@ -112,6 +122,8 @@ impl<T: Actuator + Send + 'static> ThreadedActuator<T> {
             cursor_x: 0,
             cursor_y: 0,
             tx,
+            clipboard: ClipboardPublisher::new(),
+            input_counter: Arc::new(AtomicUsize::new(0)),
             _t: PhantomData,
         }
     }
@@ -120,9 +132,25 @@ impl<T: Actuator + Send + 'static> ThreadedActuator<T> {
         self.tx.clone()
     }
 
+    pub fn get_clipboard_publisher(&self) -> ClipboardPublisher {
+        self.clipboard.clone()
+    }
+
+    /**
+     * Counts the mouse and keyboard events from the server, so other tasks can tell the user is doing something.
+     */
+    pub fn get_input_counter(&self) -> Arc<AtomicUsize> {
+        self.input_counter.clone()
+    }
+
     fn send(&self, msg: ActMsg) {
         self.tx.send(msg).unwrap()
     }
+
+    fn send_input(&self, msg: ActMsg) {
+        self.input_counter.fetch_add(1, Ordering::Relaxed);
+        self.send(msg)
+    }
 }
 
 impl<T: Actuator + Send + 'static> Actuator for ThreadedActuator<T> {
@@ -143,35 +171,35 @@ impl<T: Actuator + Send + 'static> Actuator for ThreadedActuator<T> {
     }
 
     fn set_cursor_position(&mut self, x: u16, y: u16) {
-        self.send(ActMsg::SetCursorPosition { x, y });
+        self.send_input(ActMsg::SetCursorPosition { x, y });
         self.cursor_x = x;
         self.cursor_y = y;
     }
 
     fn move_cursor(&mut self, x: i16, y: i16) {
-        self.send(ActMsg::MoveCursor { x, y });
+        self.send_input(ActMsg::MoveCursor { x, y });
         self.cursor_x = self.cursor_x.wrapping_add_signed(x);
         self.cursor_y = self.cursor_y.wrapping_add_signed(y);
     }
 
     fn mouse_down(&mut self, button: i8) {
-        self.send(ActMsg::MouseDown { button })
+        self.send_input(ActMsg::MouseDown { button })
     }
 
     fn mouse_up(&mut self, button: i8) {
-        self.send(ActMsg::MouseUp { button })
+        self.send_input(ActMsg::MouseUp { button })
     }
 
     fn mouse_wheel(&mut self, x: i16, y: i16) {
-        self.send(ActMsg::MouseWheel { x, y })
+        self.send_input(ActMsg::MouseWheel { x, y })
     }
 
     fn key_down(&mut self, key: u16, mask: u16, button: u16) {
-        self.send(ActMsg::KeyDown { key, mask, button })
+        self.send_input(ActMsg::KeyDown { key, mask, button })
     }
 
     fn key_repeat(&mut self, key: u16, mask: u16, button: u16, count: u16) {
-        self.send(ActMsg::KeyRepeat {
+        self.send_input(ActMsg::KeyRepeat {
             key,
             mask,
             button,
@@ -180,23 +208,29 @@ impl<T: Actuator + Send + 'static> Actuator for ThreadedActuator<T> {
     }
 
     fn key_up(&mut self, key: u16, mask: u16, button: u16) {
-        self.send(ActMsg::KeyUp { key, mask, button })
+        self.send_input(ActMsg::KeyUp { key, mask, button })
     }
 
     fn set_clipboard(&mut self, data: Vec<u8>) {
         self.send(ActMsg::SetClipboard { data })
     }
 
-    fn set_options(&mut self, heartbeat: u32) {
-        self.send(ActMsg::SetOptions { heartbeat })
+    fn take_clipboard(&mut self) -> Option<Vec<u8>> {
+        self.clipboard.take()
+    }
+
+    fn set_options(&mut self, options: DeviceOptions) {
+        self.send(ActMsg::SetOptions { options })
     }
 
     fn reset_options(&mut self) {
         self.send(ActMsg::ResetOptions)
     }
 
-    fn enter(&mut self) {
-        self.send(ActMsg::Enter)
+    fn enter(&mut self, x: u16, y: u16, mask: u16) {
+        self.send(ActMsg::Enter { x, y, mask });
+        self.cursor_x = x;
+        self.cursor_y = y;
     }
 
     fn leave(&mut self) {
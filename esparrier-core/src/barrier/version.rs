@@ -0,0 +1,114 @@
+use std::fmt::Display;
+
+/**
+ * The name in the hello packet, Barrier and Input Leap greet with "Barrier", Synergy 1.x and Deskflow with
+ * "Synergy", the protocol is the same. The client replies with the name the server used.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Greeting {
+    Barrier,
+    Synergy,
+}
+
+impl Greeting {
+    pub fn parse(name: &[u8]) -> Option<Self> {
+        match name {
+            b"Barrier" => Some(Self::Barrier),
+            b"Synergy" => Some(Self::Synergy),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Greeting::Barrier => "Barrier",
+            Greeting::Synergy => "Synergy",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl ProtocolVersion {
+    /**
+     * The newest version implemented by the client.
+     */
+    pub const LATEST: Self = Self::new(1, 6);
+
+    pub const fn new(major: u16, minor: u16) -> Self {
+        Self { major, minor }
+    }
+
+    /**
+     * The version to speak with a server greeting with `server`, the server handles any older minor version of the
+     * same major version. `None` if the major versions differ.
+     */
+    pub fn negotiate(server: Self) -> Option<Self> {
+        if server.major == Self::LATEST.major {
+            Some(server.min(Self::LATEST))
+        } else {
+            None
+        }
+    }
+
+    // 1.1 added the physical button to the key packets
+    pub fn has_key_button(&self) -> bool {
+        *self >= Self::new(1, 1)
+    }
+
+    // 1.3 added the keep alive, replacing the client heartbeat, and the horizontal scrolling
+    pub fn has_keep_alive(&self) -> bool {
+        *self >= Self::new(1, 3)
+    }
+
+    pub fn has_horizontal_wheel(&self) -> bool {
+        *self >= Self::new(1, 3)
+    }
+
+    // 1.6 split the clipboard into chunks
+    pub fn has_clipboard_chunks(&self) -> bool {
+        *self >= Self::new(1, 6)
+    }
+}
+
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        Self::LATEST
+    }
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Greeting, ProtocolVersion};
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(
+            ProtocolVersion::negotiate(ProtocolVersion::new(1, 8)),
+            Some(ProtocolVersion::LATEST)
+        );
+        assert_eq!(
+            ProtocolVersion::negotiate(ProtocolVersion::new(1, 4)),
+            Some(ProtocolVersion::new(1, 4))
+        );
+        assert_eq!(ProtocolVersion::negotiate(ProtocolVersion::new(2, 0)), None);
+        assert_eq!(ProtocolVersion::new(1, 4).to_string(), "1.4");
+    }
+
+    #[test]
+    fn greeting() {
+        assert_eq!(Greeting::parse(b"Synergy"), Some(Greeting::Synergy));
+        assert_eq!(Greeting::parse(b"Barrier"), Some(Greeting::Barrier));
+        assert_eq!(Greeting::parse(b"barrier"), None);
+    }
+}
@@ -0,0 +1,11 @@
+/**
+ * Fed by the client every time the server shows it's still alive.
+ */
+pub trait Watchdog {
+    fn feed(&mut self);
+}
+
+// No watchdog
+impl Watchdog for () {
+    fn feed(&mut self) {}
+}
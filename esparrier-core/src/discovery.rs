@@ -0,0 +1,74 @@
+/**
+ * The DNS-SD service types registered by the servers with Zeroconf enabled, in the order to browse.
+ */
+pub const SERVICE_TYPES: &[&str] = &[
+    "_barrierServerZeroconf",
+    "_inputLeapServerZeroconf",
+    "_synergyServerZeroconf",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerAddress<'a> {
+    /**
+     * No server configured, browse for one with DNS-SD.
+     */
+    Discover,
+    /**
+     * A `.local` host name, resolved with mDNS, without the suffix.
+     */
+    Mdns(&'a str),
+    /**
+     * An IP address or a host name resolved by the DNS server.
+     */
+    Host(&'a str),
+}
+
+impl<'a> ServerAddress<'a> {
+    pub fn parse(server: &'a str) -> Self {
+        let server = server.trim().trim_end_matches('.');
+        if server.is_empty() || server.eq_ignore_ascii_case("auto") {
+            return Self::Discover;
+        }
+        match server.len().checked_sub(".local".len()) {
+            Some(n)
+                if n > 0
+                    && server.is_char_boundary(n)
+                    && server[n..].eq_ignore_ascii_case(".local") =>
+            {
+                Self::Mdns(&server[..n])
+            }
+            _ => Self::Host(server),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServerAddress;
+
+    #[test]
+    fn parse() {
+        assert_eq!(ServerAddress::parse(""), ServerAddress::Discover);
+        assert_eq!(ServerAddress::parse(" AUTO "), ServerAddress::Discover);
+        assert_eq!(
+            ServerAddress::parse("desktop.local"),
+            ServerAddress::Mdns("desktop")
+        );
+        assert_eq!(
+            ServerAddress::parse("Desktop.LOCAL."),
+            ServerAddress::Mdns("Desktop")
+        );
+        assert_eq!(
+            ServerAddress::parse("192.168.1.2"),
+            ServerAddress::Host("192.168.1.2")
+        );
+        assert_eq!(
+            ServerAddress::parse("server.example.com"),
+            ServerAddress::Host("server.example.com")
+        );
+        assert_eq!(
+            ServerAddress::parse(".local"),
+            ServerAddress::Host(".local")
+        );
+    }
+}
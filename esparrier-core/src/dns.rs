@@ -0,0 +1,107 @@
+const HEADER_SIZE: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+}
+
+/**
+ * Builds the answer of a captive portal DNS server, every A query is answered with `ip`, other types get an
+ * empty answer. Returns `None` if the packet is not a query.
+ */
+pub fn captive_response(query: &[u8], ip: [u8; 4]) -> Option<Vec<u8>> {
+    if query.len() < HEADER_SIZE {
+        return None;
+    }
+    let flags = read_u16(query, 2)?;
+    // Only standard queries
+    if flags & 0x8000 != 0 || (flags >> 11) & 0xF != 0 || read_u16(query, 4)? == 0 {
+        return None;
+    }
+
+    // Skip the name of the first question
+    let mut pos = HEADER_SIZE;
+    loop {
+        let len = *query.get(pos)? as usize;
+        if len == 0 {
+            pos += 1;
+            break;
+        }
+        // Compression is not expected in a question
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        pos += len + 1;
+    }
+    let qtype = read_u16(query, pos)?;
+    let qclass = read_u16(query, pos + 2)?;
+    let question = &query[HEADER_SIZE..pos + 4];
+    let answer = (qtype == TYPE_A || qtype == TYPE_ANY) && qclass == CLASS_IN;
+
+    let mut ret = Vec::with_capacity(HEADER_SIZE + question.len() + 16);
+    // ID
+    ret.extend_from_slice(&query[0..2]);
+    // Response, authoritative, copy the recursion desired bit
+    ret.extend_from_slice(&(0x8400 | (flags & 0x0100)).to_be_bytes());
+    // One question, and one or no answer
+    ret.extend_from_slice(&[0, 1, 0, answer as u8, 0, 0, 0, 0]);
+    ret.extend_from_slice(question);
+    if answer {
+        // Pointer to the name in the question
+        ret.extend_from_slice(&[0xC0, HEADER_SIZE as u8]);
+        ret.extend_from_slice(&TYPE_A.to_be_bytes());
+        ret.extend_from_slice(&CLASS_IN.to_be_bytes());
+        // TTL
+        ret.extend_from_slice(&60u32.to_be_bytes());
+        ret.extend_from_slice(&4u16.to_be_bytes());
+        ret.extend_from_slice(&ip);
+    }
+    Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::captive_response;
+
+    fn query(qtype: u8) -> Vec<u8> {
+        let mut ret = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        ret.extend_from_slice(b"\x07example\x03com\x00");
+        ret.extend_from_slice(&[0, qtype, 0, 1]);
+        ret
+    }
+
+    #[test]
+    fn answer_a() {
+        let query = query(1);
+        let response = captive_response(&query, [192, 168, 71, 1]).unwrap();
+        assert_eq!(
+            &response[0..12],
+            &[0x12, 0x34, 0x85, 0x00, 0, 1, 0, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(&response[12..query.len()], &query[12..]);
+        assert_eq!(
+            &response[query.len()..],
+            &[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 168, 71, 1]
+        );
+    }
+
+    #[test]
+    fn no_answer_for_aaaa() {
+        let query = query(28);
+        let response = captive_response(&query, [192, 168, 71, 1]).unwrap();
+        assert_eq!(&response[4..8], &[0, 1, 0, 0]);
+        assert_eq!(response.len(), query.len());
+    }
+
+    #[test]
+    fn ignore_invalid() {
+        assert_eq!(captive_response(&[0; 4], [0; 4]), None);
+        let mut response = query(1);
+        response[2] |= 0x80;
+        assert_eq!(captive_response(&response, [0; 4]), None);
+        let truncated = query(1);
+        assert_eq!(captive_response(&truncated[..20], [0; 4]), None);
+    }
+}
@@ -0,0 +1,191 @@
+/**
+ * Decodes an `application/x-www-form-urlencoded` body into key/value pairs, invalid escapes are kept as they are.
+ */
+pub fn parse_form(body: &[u8]) -> Vec<(String, String)> {
+    body.split(|b| *b == b'&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut kv = pair.splitn(2, |b| *b == b'=');
+            let key = decode(kv.next().unwrap_or_default());
+            let value = decode(kv.next().unwrap_or_default());
+            (key, value)
+        })
+        .collect()
+}
+
+fn hex(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+fn decode(s: &[u8]) -> String {
+    let mut ret = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        match s[i] {
+            b'+' => ret.push(b' '),
+            b'%' if i + 2 < s.len() => match (hex(s[i + 1]), hex(s[i + 2])) {
+                (Some(h), Some(l)) => {
+                    ret.push(h << 4 | l);
+                    i += 2;
+                }
+                _ => ret.push(b'%'),
+            },
+            b => ret.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&ret).into_owned()
+}
+
+/**
+ * Escapes the text to be put into HTML content or a quoted attribute.
+ */
+pub fn escape_html(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&#39;"),
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+/**
+ * Parses a 16-bit hex number such as an USB ID, with or without the `0x` prefix.
+ */
+pub fn parse_hex_u16(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    // `from_str_radix` also takes a sign
+    if digits.is_empty() || digits.len() > 4 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn base64_value(b: u8) -> Option<u32> {
+    match b {
+        b'A'..=b'Z' => Some((b - b'A') as u32),
+        b'a'..=b'z' => Some((b - b'a' + 26) as u32),
+        b'0'..=b'9' => Some((b - b'0' + 52) as u32),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=').as_bytes();
+    let mut ret = Vec::with_capacity(s.len() * 3 / 4);
+    for chunk in s.chunks(4) {
+        if chunk.len() < 2 {
+            return None;
+        }
+        let mut bits = 0;
+        for (i, b) in chunk.iter().enumerate() {
+            bits |= base64_value(*b)? << (18 - 6 * i);
+        }
+        ret.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(ret)
+}
+
+/**
+ * The user name and the password in the `Authorization` header of HTTP basic authentication.
+ */
+pub fn basic_auth(header: &str) -> Option<(String, String)> {
+    let (scheme, credentials) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let credentials = String::from_utf8(decode_base64(credentials.trim())?).ok()?;
+    let (user, password) = credentials.split_once(':')?;
+    Some((user.to_owned(), password.to_owned()))
+}
+
+/**
+ * Compares the secrets in a time that doesn't depend on where they differ.
+ */
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{basic_auth, constant_time_eq, escape_html, parse_form, parse_hex_u16};
+
+    #[test]
+    fn form() {
+        assert_eq!(
+            parse_form(b"ssid=My+WiFi&pass=p%40ss%26word&empty=&bad=100%&flag"),
+            vec![
+                ("ssid".to_owned(), "My WiFi".to_owned()),
+                ("pass".to_owned(), "p@ss&word".to_owned()),
+                ("empty".to_owned(), "".to_owned()),
+                ("bad".to_owned(), "100%".to_owned()),
+                ("flag".to_owned(), "".to_owned()),
+            ]
+        );
+        assert_eq!(
+            parse_form("name=%E4%BD%A0%E5%A5%BD".as_bytes()),
+            vec![("name".to_owned(), "你好".to_owned())]
+        );
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(
+            escape_html("<a href=\"x\">&'"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;"
+        );
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse_hex_u16("0x303A"), Some(0x303a));
+        assert_eq!(parse_hex_u16("303a"), Some(0x303a));
+        assert_eq!(parse_hex_u16("0Xc0de"), Some(0xc0de));
+        assert_eq!(parse_hex_u16("0x0d0a"), Some(0x0d0a));
+        assert_eq!(parse_hex_u16("0x"), None);
+        assert_eq!(parse_hex_u16("0x12346"), None);
+        assert_eq!(parse_hex_u16("+123"), None);
+        assert_eq!(parse_hex_u16("xyz"), None);
+    }
+
+    #[test]
+    fn auth() {
+        // admin:secret, Aladdin:open sesame and a password with a colon
+        assert_eq!(
+            basic_auth("Basic YWRtaW46c2VjcmV0"),
+            Some(("admin".to_owned(), "secret".to_owned()))
+        );
+        assert_eq!(
+            basic_auth("basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="),
+            Some(("Aladdin".to_owned(), "open sesame".to_owned()))
+        );
+        assert_eq!(
+            basic_auth("Basic YTpiOmM="),
+            Some(("a".to_owned(), "b:c".to_owned()))
+        );
+        assert_eq!(basic_auth("Bearer YWRtaW46c2VjcmV0"), None);
+        assert_eq!(basic_auth("Basic YWRtaW4="), None);
+        assert_eq!(basic_auth("Basic !!!!"), None);
+        assert_eq!(basic_auth("Basic"), None);
+    }
+
+    #[test]
+    fn secret_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret1"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -0,0 +1,311 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::keycodes::*;
+
+/**
+ * Modifier flags of a key in the layout tables.
+ */
+pub const SHIFT: u8 = 1;
+pub const ALT_GR: u8 = 2;
+// Dead key, followed by Space to type the character itself
+pub const DEAD: u8 = 4;
+
+/**
+ * The key and modifiers to type a character on the host with the given layout.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stroke {
+    pub key: u8,
+    pub flags: u8,
+}
+
+/**
+ * Keyboard layout configured on the host, the HID usages are positions on the keyboard, so the same usage types
+ * different characters in different layouts.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyboardLayout {
+    #[default]
+    Us,
+    Uk,
+    De,
+    Fr,
+    Dvorak,
+    Jis,
+}
+
+impl FromStr for KeyboardLayout {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "us" | "" => Ok(Self::Us),
+            "uk" | "gb" => Ok(Self::Uk),
+            "de" => Ok(Self::De),
+            "fr" => Ok(Self::Fr),
+            "dvorak" => Ok(Self::Dvorak),
+            "jis" | "jp" => Ok(Self::Jis),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for KeyboardLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            KeyboardLayout::Us => "us",
+            KeyboardLayout::Uk => "uk",
+            KeyboardLayout::De => "de",
+            KeyboardLayout::Fr => "fr",
+            KeyboardLayout::Dvorak => "dvorak",
+            KeyboardLayout::Jis => "jis",
+        })
+    }
+}
+
+// The characters that are typed differently from the US layout, other ASCII characters use the US table
+const UK: &[(char, u8, u8)] = &[
+    ('"', HID_KEY_2, SHIFT),
+    ('@', HID_KEY_APOSTROPHE, SHIFT),
+    ('#', HID_KEY_EUROPE_1, 0),
+    ('~', HID_KEY_EUROPE_1, SHIFT),
+    ('\\', HID_KEY_EUROPE_2, 0),
+    ('|', HID_KEY_EUROPE_2, SHIFT),
+    ('£', HID_KEY_3, SHIFT),
+    ('¬', HID_KEY_GRAVE, SHIFT),
+    ('€', HID_KEY_4, ALT_GR),
+];
+
+const DE: &[(char, u8, u8)] = &[
+    ('"', HID_KEY_2, SHIFT),
+    ('#', HID_KEY_EUROPE_1, 0),
+    ('&', HID_KEY_6, SHIFT),
+    ('\'', HID_KEY_EUROPE_1, SHIFT),
+    ('(', HID_KEY_8, SHIFT),
+    (')', HID_KEY_9, SHIFT),
+    ('*', HID_KEY_BRACKET_RIGHT, SHIFT),
+    ('+', HID_KEY_BRACKET_RIGHT, 0),
+    ('-', HID_KEY_SLASH, 0),
+    ('/', HID_KEY_7, SHIFT),
+    (':', HID_KEY_PERIOD, SHIFT),
+    (';', HID_KEY_COMMA, SHIFT),
+    ('<', HID_KEY_EUROPE_2, 0),
+    ('=', HID_KEY_0, SHIFT),
+    ('>', HID_KEY_EUROPE_2, SHIFT),
+    ('?', HID_KEY_MINUS, SHIFT),
+    ('@', HID_KEY_Q, ALT_GR),
+    ('Y', HID_KEY_Z, SHIFT),
+    ('Z', HID_KEY_Y, SHIFT),
+    ('[', HID_KEY_8, ALT_GR),
+    ('\\', HID_KEY_MINUS, ALT_GR),
+    (']', HID_KEY_9, ALT_GR),
+    ('^', HID_KEY_GRAVE, DEAD),
+    ('_', HID_KEY_SLASH, SHIFT),
+    ('`', HID_KEY_EQUAL, SHIFT | DEAD),
+    ('y', HID_KEY_Z, 0),
+    ('z', HID_KEY_Y, 0),
+    ('{', HID_KEY_7, ALT_GR),
+    ('|', HID_KEY_EUROPE_2, ALT_GR),
+    ('}', HID_KEY_0, ALT_GR),
+    ('~', HID_KEY_BRACKET_RIGHT, ALT_GR),
+    ('ä', HID_KEY_APOSTROPHE, 0),
+    ('Ä', HID_KEY_APOSTROPHE, SHIFT),
+    ('ö', HID_KEY_SEMICOLON, 0),
+    ('Ö', HID_KEY_SEMICOLON, SHIFT),
+    ('ü', HID_KEY_BRACKET_LEFT, 0),
+    ('Ü', HID_KEY_BRACKET_LEFT, SHIFT),
+    ('ß', HID_KEY_MINUS, 0),
+    ('§', HID_KEY_3, SHIFT),
+    ('°', HID_KEY_GRAVE, SHIFT),
+    ('´', HID_KEY_EQUAL, DEAD),
+    ('²', HID_KEY_2, ALT_GR),
+    ('³', HID_KEY_3, ALT_GR),
+    ('µ', HID_KEY_M, ALT_GR),
+    ('€', HID_KEY_E, ALT_GR),
+];
+
+const FR: &[(char, u8, u8)] = &[
+    ('!', HID_KEY_SLASH, 0),
+    ('"', HID_KEY_3, 0),
+    ('#', HID_KEY_3, ALT_GR),
+    ('$', HID_KEY_BRACKET_RIGHT, 0),
+    ('%', HID_KEY_APOSTROPHE, SHIFT),
+    ('&', HID_KEY_1, 0),
+    ('\'', HID_KEY_4, 0),
+    ('(', HID_KEY_5, 0),
+    (')', HID_KEY_MINUS, 0),
+    ('*', HID_KEY_EUROPE_1, 0),
+    ('+', HID_KEY_EQUAL, SHIFT),
+    (',', HID_KEY_M, 0),
+    ('-', HID_KEY_6, 0),
+    ('.', HID_KEY_COMMA, SHIFT),
+    ('/', HID_KEY_PERIOD, SHIFT),
+    ('0', HID_KEY_0, SHIFT),
+    ('1', HID_KEY_1, SHIFT),
+    ('2', HID_KEY_2, SHIFT),
+    ('3', HID_KEY_3, SHIFT),
+    ('4', HID_KEY_4, SHIFT),
+    ('5', HID_KEY_5, SHIFT),
+    ('6', HID_KEY_6, SHIFT),
+    ('7', HID_KEY_7, SHIFT),
+    ('8', HID_KEY_8, SHIFT),
+    ('9', HID_KEY_9, SHIFT),
+    (':', HID_KEY_PERIOD, 0),
+    (';', HID_KEY_COMMA, 0),
+    ('<', HID_KEY_EUROPE_2, 0),
+    ('=', HID_KEY_EQUAL, 0),
+    ('>', HID_KEY_EUROPE_2, SHIFT),
+    ('?', HID_KEY_M, SHIFT),
+    ('@', HID_KEY_0, ALT_GR),
+    ('A', HID_KEY_Q, SHIFT),
+    ('M', HID_KEY_SEMICOLON, SHIFT),
+    ('Q', HID_KEY_A, SHIFT),
+    ('W', HID_KEY_Z, SHIFT),
+    ('Z', HID_KEY_W, SHIFT),
+    ('[', HID_KEY_5, ALT_GR),
+    ('\\', HID_KEY_8, ALT_GR),
+    (']', HID_KEY_MINUS, ALT_GR),
+    ('^', HID_KEY_9, ALT_GR),
+    ('_', HID_KEY_8, 0),
+    ('`', HID_KEY_7, ALT_GR | DEAD),
+    ('a', HID_KEY_Q, 0),
+    ('m', HID_KEY_SEMICOLON, 0),
+    ('q', HID_KEY_A, 0),
+    ('w', HID_KEY_Z, 0),
+    ('z', HID_KEY_W, 0),
+    ('{', HID_KEY_4, ALT_GR),
+    ('|', HID_KEY_6, ALT_GR),
+    ('}', HID_KEY_EQUAL, ALT_GR),
+    ('~', HID_KEY_2, ALT_GR | DEAD),
+    ('é', HID_KEY_2, 0),
+    ('è', HID_KEY_7, 0),
+    ('ç', HID_KEY_9, 0),
+    ('à', HID_KEY_0, 0),
+    ('ù', HID_KEY_APOSTROPHE, 0),
+    ('°', HID_KEY_MINUS, SHIFT),
+    ('£', HID_KEY_BRACKET_RIGHT, SHIFT),
+    ('¤', HID_KEY_BRACKET_RIGHT, ALT_GR),
+    ('§', HID_KEY_SLASH, SHIFT),
+    ('µ', HID_KEY_EUROPE_1, SHIFT),
+    ('²', HID_KEY_GRAVE, 0),
+    ('€', HID_KEY_E, ALT_GR),
+];
+
+const JIS: &[(char, u8, u8)] = &[
+    ('"', HID_KEY_2, SHIFT),
+    ('&', HID_KEY_6, SHIFT),
+    ('\'', HID_KEY_7, SHIFT),
+    ('(', HID_KEY_8, SHIFT),
+    (')', HID_KEY_9, SHIFT),
+    ('*', HID_KEY_APOSTROPHE, SHIFT),
+    ('+', HID_KEY_SEMICOLON, SHIFT),
+    (':', HID_KEY_APOSTROPHE, 0),
+    ('=', HID_KEY_MINUS, SHIFT),
+    ('@', HID_KEY_BRACKET_LEFT, 0),
+    ('[', HID_KEY_BRACKET_RIGHT, 0),
+    ('\\', HID_KEY_KANJI1, 0),
+    (']', HID_KEY_EUROPE_1, 0),
+    ('^', HID_KEY_EQUAL, 0),
+    ('_', HID_KEY_KANJI1, SHIFT),
+    ('`', HID_KEY_BRACKET_LEFT, SHIFT),
+    ('{', HID_KEY_BRACKET_RIGHT, SHIFT),
+    ('|', HID_KEY_KANJI3, SHIFT),
+    ('}', HID_KEY_EUROPE_1, SHIFT),
+    ('~', HID_KEY_EQUAL, SHIFT),
+    ('¥', HID_KEY_KANJI3, 0),
+];
+
+// Dvorak only moves the keys around, the character at the same position in `DVORAK` is typed by the key of
+// the character in `QWERTY`
+const QWERTY: &str = "-=qwertyuiop[]asdfghjkl;'zxcvbnm,./_+QWERTYUIOP{}ASDFGHJKL:\"ZXCVBNM<>?";
+const DVORAK: &str = "[]',.pyfgcrl/=aoeuidhtns-;qjkxbmwvz{}\"<>PYFGCRL?+AOEUIDHTNS_:QJKXBMWVZ";
+
+fn us(c: char) -> Option<Stroke> {
+    if !c.is_ascii() {
+        return None;
+    }
+    let [key, modifier] = ASCII_2_HID[c as usize];
+    if key == 0 {
+        return None;
+    }
+    let flags = if modifier == HID_KEY_SHIFT_LEFT {
+        SHIFT
+    } else {
+        0
+    };
+    Some(Stroke { key, flags })
+}
+
+fn find(table: &[(char, u8, u8)], c: char) -> Option<Stroke> {
+    table
+        .iter()
+        .find(|(ch, _, _)| *ch == c)
+        .map(|(_, key, flags)| Stroke {
+            key: *key,
+            flags: *flags,
+        })
+}
+
+impl KeyboardLayout {
+    /**
+     * Returns the key to type the character, or `None` if the layout doesn't have it.
+     */
+    pub fn lookup(self, c: char) -> Option<Stroke> {
+        match self {
+            KeyboardLayout::Us => us(c),
+            KeyboardLayout::Uk => find(UK, c).or_else(|| us(c)),
+            KeyboardLayout::De => find(DE, c).or_else(|| us(c)),
+            KeyboardLayout::Fr => find(FR, c).or_else(|| us(c)),
+            KeyboardLayout::Jis => find(JIS, c).or_else(|| us(c)),
+            KeyboardLayout::Dvorak => match DVORAK.chars().position(|ch| ch == c) {
+                Some(idx) => QWERTY.chars().nth(idx).and_then(us),
+                None => us(c),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_have_no_duplicates() {
+        for table in [UK, DE, FR, JIS] {
+            for (i, (c, _, _)) in table.iter().enumerate() {
+                assert!(table[i + 1..].iter().all(|(ch, _, _)| ch != c), "{c}");
+            }
+        }
+        assert_eq!(QWERTY.len(), DVORAK.len());
+    }
+
+    #[test]
+    fn lookup() {
+        let stroke = |key, flags| Some(Stroke { key, flags });
+        assert_eq!(KeyboardLayout::Us.lookup('@'), stroke(HID_KEY_2, SHIFT));
+        assert_eq!(
+            KeyboardLayout::Uk.lookup('@'),
+            stroke(HID_KEY_APOSTROPHE, SHIFT)
+        );
+        assert_eq!(KeyboardLayout::De.lookup('@'), stroke(HID_KEY_Q, ALT_GR));
+        assert_eq!(KeyboardLayout::De.lookup('z'), stroke(HID_KEY_Y, 0));
+        assert_eq!(KeyboardLayout::De.lookup('a'), stroke(HID_KEY_A, 0));
+        assert_eq!(KeyboardLayout::Fr.lookup('a'), stroke(HID_KEY_Q, 0));
+        assert_eq!(KeyboardLayout::Fr.lookup('1'), stroke(HID_KEY_1, SHIFT));
+        assert_eq!(
+            KeyboardLayout::Dvorak.lookup('s'),
+            stroke(HID_KEY_SEMICOLON, 0)
+        );
+        assert_eq!(
+            KeyboardLayout::Dvorak.lookup('W'),
+            stroke(HID_KEY_COMMA, SHIFT)
+        );
+        assert_eq!(KeyboardLayout::Dvorak.lookup('1'), stroke(HID_KEY_1, 0));
+        assert_eq!(
+            KeyboardLayout::Jis.lookup('@'),
+            stroke(HID_KEY_BRACKET_LEFT, 0)
+        );
+        assert_eq!(KeyboardLayout::Us.lookup('é'), None);
+        assert_eq!(KeyboardLayout::Fr.lookup('é'), stroke(HID_KEY_2, 0));
+    }
+}
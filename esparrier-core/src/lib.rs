@@ -0,0 +1,13 @@
+//! Platform independent part of Esparrier: the Barrier protocol client, the keycode tables and the HID report
+//! state, no ESP-IDF dependencies so it can be built and tested on the host.
+
+pub mod backoff;
+pub mod barrier;
+pub mod discovery;
+pub mod dns;
+pub mod form;
+pub mod keycodes;
+pub mod layout;
+pub mod paste;
+pub mod reports;
+pub mod wifi;
@@ -0,0 +1,421 @@
+use std::{collections::VecDeque, fmt::Display, str::FromStr, time::Duration};
+
+use crate::{
+    keycodes::{
+        HID_KEY_ALT_LEFT, HID_KEY_ALT_RIGHT, HID_KEY_CONTROL_LEFT, HID_KEY_KEYPAD_0,
+        HID_KEY_KEYPAD_1, HID_KEY_SHIFT_LEFT, HID_KEY_SPACE,
+    },
+    layout::{KeyboardLayout, Stroke, ALT_GR, DEAD, SHIFT},
+};
+
+/**
+ * HID key events sent to the host to type the text.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    Down(u8),
+    Up(u8),
+}
+
+/**
+ * How to type characters that don't have a key in the keyboard layout, all of them depend on the host.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnicodeInput {
+    /**
+     * Drop the character.
+     */
+    #[default]
+    Skip,
+    /**
+     * Hold Alt and type the decimal code point on the numpad, needs NumLock on, and only applications using the
+     * rich edit controls accept code points above 255.
+     */
+    Windows,
+    /**
+     * Ctrl+Shift+U, the hex code point and Space, supported by IBus and GTK applications.
+     */
+    Linux,
+    /**
+     * Hold Option and type the hex UTF-16 code units, needs the "Unicode Hex Input" source selected.
+     */
+    MacOS,
+}
+
+impl FromStr for UnicodeInput {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "skip" | "none" | "" => Ok(Self::Skip),
+            "windows" | "win" => Ok(Self::Windows),
+            "linux" => Ok(Self::Linux),
+            "macos" | "mac" => Ok(Self::MacOS),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for UnicodeInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            UnicodeInput::Skip => "skip",
+            UnicodeInput::Windows => "windows",
+            UnicodeInput::Linux => "linux",
+            UnicodeInput::MacOS => "macos",
+        })
+    }
+}
+
+fn tap(key: u8, out: &mut impl FnMut(KeyEvent)) {
+    out(KeyEvent::Down(key));
+    out(KeyEvent::Up(key));
+}
+
+fn type_stroke(stroke: Stroke, out: &mut impl FnMut(KeyEvent)) {
+    let modifiers = [(SHIFT, HID_KEY_SHIFT_LEFT), (ALT_GR, HID_KEY_ALT_RIGHT)];
+    for (flag, key) in modifiers {
+        if stroke.flags & flag != 0 {
+            out(KeyEvent::Down(key));
+        }
+    }
+    tap(stroke.key, out);
+    for (flag, key) in modifiers.into_iter().rev() {
+        if stroke.flags & flag != 0 {
+            out(KeyEvent::Up(key));
+        }
+    }
+    if stroke.flags & DEAD != 0 {
+        tap(HID_KEY_SPACE, out);
+    }
+}
+
+fn type_hex(value: u32, layout: KeyboardLayout, out: &mut impl FnMut(KeyEvent)) {
+    for c in format!("{:04x}", value).chars() {
+        if let Some(stroke) = layout.lookup(c) {
+            type_stroke(stroke, out);
+        }
+    }
+}
+
+/**
+ * Generates the key events to type the character, characters in the layout are typed directly, others with
+ * `input`.
+ */
+pub fn type_char(
+    c: char,
+    layout: KeyboardLayout,
+    input: UnicodeInput,
+    out: &mut impl FnMut(KeyEvent),
+) {
+    if let Some(stroke) = layout.lookup(c) {
+        type_stroke(stroke, out);
+        return;
+    }
+    match input {
+        UnicodeInput::Skip => {}
+        UnicodeInput::Windows => {
+            // The leading 0 selects the ANSI code page instead of the OEM one for code points below 256
+            let code = c as u32;
+            let digits = if code < 256 {
+                format!("0{}", code)
+            } else {
+                code.to_string()
+            };
+            out(KeyEvent::Down(HID_KEY_ALT_LEFT));
+            for d in digits.bytes() {
+                let key = match d {
+                    b'0' => HID_KEY_KEYPAD_0,
+                    _ => HID_KEY_KEYPAD_1 + (d - b'1'),
+                };
+                tap(key, out);
+            }
+            out(KeyEvent::Up(HID_KEY_ALT_LEFT));
+        }
+        UnicodeInput::Linux => {
+            // IBus looks at the key symbol, so the U is where the layout has it
+            let Some(u) = layout.lookup('u') else {
+                return;
+            };
+            out(KeyEvent::Down(HID_KEY_CONTROL_LEFT));
+            out(KeyEvent::Down(HID_KEY_SHIFT_LEFT));
+            tap(u.key, out);
+            out(KeyEvent::Up(HID_KEY_SHIFT_LEFT));
+            out(KeyEvent::Up(HID_KEY_CONTROL_LEFT));
+            type_hex(c as u32, layout, out);
+            tap(HID_KEY_SPACE, out);
+        }
+        UnicodeInput::MacOS => {
+            let mut buf = [0; 2];
+            out(KeyEvent::Down(HID_KEY_ALT_LEFT));
+            // Characters outside of BMP are typed as surrogate pairs, Unicode Hex Input is based on the US layout
+            for unit in c.encode_utf16(&mut buf) {
+                type_hex(*unit as u32, KeyboardLayout::Us, out);
+            }
+            out(KeyEvent::Up(HID_KEY_ALT_LEFT));
+        }
+    }
+}
+
+// Decodes the next character of the UTF-8 text, invalid sequences are skipped, returns `None` at the end or NUL
+fn next_char(text: &mut &[u8]) -> Option<char> {
+    while !text.is_empty() {
+        // A UTF-8 sequence is at most 4 bytes
+        let head = &text[..text.len().min(4)];
+        let valid = match std::str::from_utf8(head) {
+            Ok(s) => s,
+            Err(e) if e.valid_up_to() > 0 => {
+                std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default()
+            }
+            Err(e) => {
+                // A truncated sequence at the end has no error length
+                let bad = e.error_len().unwrap_or(head.len());
+                *text = &text[bad..];
+                continue;
+            }
+        };
+        let c = valid.chars().next()?;
+        *text = &text[c.len_utf8()..];
+        return if c == '\0' { None } else { Some(c) };
+    }
+    None
+}
+
+/**
+ * Generates the key events to type the UTF-8 text, stops at the first NUL, invalid sequences are skipped.
+ */
+pub fn type_text(
+    mut text: &[u8],
+    layout: KeyboardLayout,
+    input: UnicodeInput,
+    out: &mut impl FnMut(KeyEvent),
+) {
+    while let Some(c) = next_char(&mut text) {
+        type_char(c, layout, input, out);
+    }
+}
+
+/**
+ * Delays between the key events when pasting, slow hosts, remote desktops and BIOS screens drop keys sent too fast.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PasteTiming {
+    pub key_delay: Duration,
+    pub newline_delay: Duration,
+}
+
+/**
+ * The key events to paste the text, each with the delay to wait after sending it, generated one character at a
+ * time so the whole sequence doesn't need to be kept in memory.
+ */
+pub struct Paste<'a> {
+    text: &'a [u8],
+    layout: KeyboardLayout,
+    input: UnicodeInput,
+    timing: PasteTiming,
+    pending: VecDeque<(KeyEvent, Duration)>,
+}
+
+impl<'a> Paste<'a> {
+    pub fn new(
+        text: &'a [u8],
+        layout: KeyboardLayout,
+        input: UnicodeInput,
+        timing: PasteTiming,
+    ) -> Self {
+        Self {
+            text,
+            layout,
+            input,
+            timing,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<'a> Iterator for Paste<'a> {
+    type Item = (KeyEvent, Duration);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let c = next_char(&mut self.text)?;
+            let delay = self.timing.key_delay;
+            type_char(c, self.layout, self.input, &mut |e| {
+                self.pending.push_back((e, delay))
+            });
+            if c == '\n' {
+                if let Some((_, delay)) = self.pending.back_mut() {
+                    *delay += self.timing.newline_delay;
+                }
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{type_text, KeyEvent, KeyboardLayout, Paste, PasteTiming, UnicodeInput};
+    use crate::keycodes::*;
+
+    fn keys(text: &str, input: UnicodeInput) -> Vec<KeyEvent> {
+        keys_with(text, KeyboardLayout::Us, input)
+    }
+
+    fn keys_with(text: &str, layout: KeyboardLayout, input: UnicodeInput) -> Vec<KeyEvent> {
+        let mut ret = vec![];
+        type_text(text.as_bytes(), layout, input, &mut |e| ret.push(e));
+        ret
+    }
+
+    // Only the key downs, which is enough to tell the sequences apart
+    fn downs(text: &str, input: UnicodeInput) -> Vec<u8> {
+        keys(text, input)
+            .into_iter()
+            .filter_map(|e| match e {
+                KeyEvent::Down(k) => Some(k),
+                KeyEvent::Up(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ascii() {
+        assert_eq!(
+            keys("aB", UnicodeInput::Skip),
+            vec![
+                KeyEvent::Down(HID_KEY_A),
+                KeyEvent::Up(HID_KEY_A),
+                KeyEvent::Down(HID_KEY_SHIFT_LEFT),
+                KeyEvent::Down(HID_KEY_B),
+                KeyEvent::Up(HID_KEY_B),
+                KeyEvent::Up(HID_KEY_SHIFT_LEFT),
+            ]
+        );
+        assert_eq!(downs("é", UnicodeInput::Skip), vec![]);
+    }
+
+    #[test]
+    fn windows() {
+        // U+00E9 = 233
+        assert_eq!(
+            downs("é", UnicodeInput::Windows),
+            vec![
+                HID_KEY_ALT_LEFT,
+                HID_KEY_KEYPAD_0,
+                HID_KEY_KEYPAD_2,
+                HID_KEY_KEYPAD_3,
+                HID_KEY_KEYPAD_3
+            ]
+        );
+    }
+
+    #[test]
+    fn linux() {
+        assert_eq!(
+            downs("€", UnicodeInput::Linux),
+            vec![
+                HID_KEY_CONTROL_LEFT,
+                HID_KEY_SHIFT_LEFT,
+                HID_KEY_U,
+                HID_KEY_2,
+                HID_KEY_0,
+                HID_KEY_A,
+                HID_KEY_C,
+                HID_KEY_SPACE
+            ]
+        );
+    }
+
+    #[test]
+    fn macos_surrogate_pair() {
+        // U+1F600 = D83D DE00
+        assert_eq!(
+            downs("😀", UnicodeInput::MacOS),
+            vec![
+                HID_KEY_ALT_LEFT,
+                HID_KEY_D,
+                HID_KEY_8,
+                HID_KEY_3,
+                HID_KEY_D,
+                HID_KEY_D,
+                HID_KEY_E,
+                HID_KEY_0,
+                HID_KEY_0
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut ret = vec![];
+        type_text(
+            b"a\xFFb\xE2\x82",
+            KeyboardLayout::Us,
+            UnicodeInput::Linux,
+            &mut |e| ret.push(e),
+        );
+        assert_eq!(
+            ret,
+            [keys("a", UnicodeInput::Skip), keys("b", UnicodeInput::Skip)].concat()
+        );
+    }
+
+    #[test]
+    fn layout() {
+        // AltGr+Q on German keyboards
+        assert_eq!(
+            keys_with("@", KeyboardLayout::De, UnicodeInput::Skip),
+            vec![
+                KeyEvent::Down(HID_KEY_ALT_RIGHT),
+                KeyEvent::Down(HID_KEY_Q),
+                KeyEvent::Up(HID_KEY_Q),
+                KeyEvent::Up(HID_KEY_ALT_RIGHT),
+            ]
+        );
+        // Dead key followed by Space
+        assert_eq!(
+            keys_with("^", KeyboardLayout::De, UnicodeInput::Skip),
+            vec![
+                KeyEvent::Down(HID_KEY_GRAVE),
+                KeyEvent::Up(HID_KEY_GRAVE),
+                KeyEvent::Down(HID_KEY_SPACE),
+                KeyEvent::Up(HID_KEY_SPACE),
+            ]
+        );
+        // In the layout, no need for the input method
+        assert_eq!(
+            keys_with("é", KeyboardLayout::Fr, UnicodeInput::Linux),
+            vec![KeyEvent::Down(HID_KEY_2), KeyEvent::Up(HID_KEY_2)]
+        );
+    }
+
+    #[test]
+    fn stops_at_nul() {
+        assert_eq!(downs("a\0b", UnicodeInput::Skip), vec![HID_KEY_A]);
+    }
+
+    #[test]
+    fn paste_timing() {
+        let timing = PasteTiming {
+            key_delay: Duration::from_millis(5),
+            newline_delay: Duration::from_millis(100),
+        };
+        let paste: Vec<_> =
+            Paste::new(b"a\nb", KeyboardLayout::Us, UnicodeInput::Skip, timing).collect();
+        let ms = Duration::from_millis;
+        assert_eq!(
+            paste,
+            vec![
+                (KeyEvent::Down(HID_KEY_A), ms(5)),
+                (KeyEvent::Up(HID_KEY_A), ms(5)),
+                (KeyEvent::Down(HID_KEY_ENTER), ms(5)),
+                (KeyEvent::Up(HID_KEY_ENTER), ms(105)),
+                (KeyEvent::Down(HID_KEY_B), ms(5)),
+                (KeyEvent::Up(HID_KEY_B), ms(5)),
+            ]
+        );
+    }
+}
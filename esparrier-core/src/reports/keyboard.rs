@@ -0,0 +1,131 @@
+/**
+ * State of the boot protocol keyboard report, the caller sends `modifier()` and `keycode()` after each change.
+ */
+pub struct KeyboardReport<const N: usize> {
+    modifier: u8,
+    keycode: [u8; N],
+}
+
+impl<const N: usize> KeyboardReport<N> {
+    pub fn new() -> Self {
+        Self {
+            modifier: 0,
+            keycode: [0; N],
+        }
+    }
+
+    pub fn press(&mut self, key: u8) {
+        match self.get_modifier(key) {
+            Some(modifier) => self.modifier |= modifier,
+            None => {
+                // Don't add the same key twice
+                for i in 0..N {
+                    if self.keycode[i] == key {
+                        return;
+                    }
+                }
+
+                let mut found = false;
+                for i in 0..N {
+                    if self.keycode[i] == 0 {
+                        self.keycode[i] = key;
+                        found = true;
+                        break;
+                    }
+                }
+                if !found {
+                    // roll over the first key
+                    for i in 1..N {
+                        self.keycode.swap(i - 1, i);
+                    }
+                    self.keycode[N - 1] = key;
+                }
+            }
+        }
+    }
+
+    pub fn release(&mut self, key: u8) {
+        match self.get_modifier(key) {
+            Some(modifier) => self.modifier &= !modifier,
+            None => {
+                for i in 0..N {
+                    if self.keycode[i] == key {
+                        self.keycode[i] = 0;
+                        break;
+                    }
+                }
+                // Compact the keycode array
+                let mut pos = 0;
+                for i in 0..N {
+                    if self.keycode[i] != 0 {
+                        self.keycode.swap(i, pos);
+                        pos += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.modifier = 0;
+        self.keycode = [0; N];
+    }
+
+    pub fn modifier(&self) -> u8 {
+        self.modifier
+    }
+
+    pub fn keycode(&self) -> &[u8; N] {
+        &self.keycode
+    }
+
+    fn get_modifier(&self, key: u8) -> Option<u8> {
+        match key {
+            0xE0 => Some(0x01), // Left Control
+            0xE1 => Some(0x02), // Left Shift
+            0xE2 => Some(0x04), // Left Alt
+            0xE3 => Some(0x08), // Left GUI
+            0xE4 => Some(0x10), // Right Control
+            0xE5 => Some(0x20), // Right Shift
+            0xE6 => Some(0x40), // Right Alt
+            0xE7 => Some(0x80), // Right GUI
+            _ => None,
+        }
+    }
+}
+
+impl<const N: usize> Default for KeyboardReport<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyboardReport;
+
+    #[test]
+    fn press_and_release() {
+        let mut report = KeyboardReport::<6>::new();
+        report.press(0xE1);
+        report.press(0x04);
+        report.press(0x04);
+        report.press(0x05);
+        assert_eq!(report.modifier(), 0x02);
+        assert_eq!(report.keycode(), &[0x04, 0x05, 0, 0, 0, 0]);
+
+        report.release(0x04);
+        report.release(0xE1);
+        assert_eq!(report.modifier(), 0);
+        assert_eq!(report.keycode(), &[0x05, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn roll_over() {
+        let mut report = KeyboardReport::<2>::new();
+        report.press(0x04);
+        report.press(0x05);
+        report.press(0x06);
+        assert_eq!(report.keycode(), &[0x05, 0x06]);
+    }
+}
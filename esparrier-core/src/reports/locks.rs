@@ -0,0 +1,95 @@
+use crate::keycodes::{HID_KEY_CAPS_LOCK, HID_KEY_NUM_LOCK, HID_KEY_SCROLL_LOCK};
+
+// The lock bits of the Barrier `KeyModifierMask`
+const MASK_CAPS_LOCK: u16 = 0x1000;
+const MASK_NUM_LOCK: u16 = 0x2000;
+const MASK_SCROLL_LOCK: u16 = 0x4000;
+
+// The bits of the HID keyboard LED output report
+pub const LED_NUM_LOCK: u8 = 0x01;
+pub const LED_CAPS_LOCK: u8 = 0x02;
+pub const LED_SCROLL_LOCK: u8 = 0x04;
+
+const LOCKS: [(u16, u8, u8); 3] = [
+    (MASK_CAPS_LOCK, LED_CAPS_LOCK, HID_KEY_CAPS_LOCK),
+    (MASK_NUM_LOCK, LED_NUM_LOCK, HID_KEY_NUM_LOCK),
+    (MASK_SCROLL_LOCK, LED_SCROLL_LOCK, HID_KEY_SCROLL_LOCK),
+];
+
+/**
+ * The lock state of the host, as the keyboard LEDs. It is set from the LEDs reported by the host, and tracked from
+ * the lock keys pressed by the board in between.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LockState {
+    leds: u8,
+}
+
+impl LockState {
+    pub fn leds(&self) -> u8 {
+        self.leds
+    }
+
+    pub fn set_leds(&mut self, leds: u8) {
+        self.leds = leds;
+    }
+
+    /**
+     * Updates the state after the board pressed `key`.
+     */
+    pub fn pressed(&mut self, key: u8) {
+        for (_, led, lock_key) in LOCKS {
+            if key == lock_key {
+                self.leds ^= led;
+            }
+        }
+    }
+
+    /**
+     * Whether the lock of `key` is on, false for the other keys.
+     */
+    pub fn is_locked(&self, key: u8) -> bool {
+        LOCKS
+            .iter()
+            .any(|(_, led, lock_key)| key == *lock_key && self.leds & led != 0)
+    }
+
+    /**
+     * The lock keys to press and release so the host matches the locks in `mask`.
+     */
+    pub fn keys_to_match(&self, mask: u16) -> Vec<u8> {
+        LOCKS
+            .iter()
+            .filter(|(mask_bit, led, _)| (mask & mask_bit != 0) != (self.leds & led != 0))
+            .map(|(_, _, key)| *key)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_mask() {
+        let mut locks = LockState::default();
+        assert_eq!(locks.keys_to_match(0), vec![]);
+        // Shift and Caps Lock
+        assert_eq!(locks.keys_to_match(0x1001), vec![HID_KEY_CAPS_LOCK]);
+        locks.pressed(HID_KEY_CAPS_LOCK);
+        assert_eq!(locks.leds(), LED_CAPS_LOCK);
+        assert_eq!(locks.keys_to_match(0x1001), vec![]);
+
+        locks.set_leds(LED_NUM_LOCK | LED_SCROLL_LOCK);
+        assert_eq!(
+            locks.keys_to_match(0x3000),
+            vec![HID_KEY_CAPS_LOCK, HID_KEY_SCROLL_LOCK]
+        );
+        locks.pressed(HID_KEY_SCROLL_LOCK);
+        locks.pressed(0x04);
+        assert_eq!(locks.leds(), LED_NUM_LOCK);
+        assert!(locks.is_locked(HID_KEY_NUM_LOCK));
+        assert!(!locks.is_locked(HID_KEY_CAPS_LOCK));
+        assert!(!locks.is_locked(0x04));
+    }
+}
@@ -0,0 +1,9 @@
+mod keyboard;
+mod locks;
+mod mouse;
+mod usb;
+
+pub use keyboard::KeyboardReport;
+pub use locks::LockState;
+pub use mouse::{absolute_move, relative_move, split_move, MouseMode};
+pub use usb::{ReportError, UsbState};
@@ -0,0 +1,127 @@
+use std::{fmt::Display, str::FromStr};
+
+// The range of the absolute pointer
+const ABS_MAX: i32 = 0x7fff;
+
+/**
+ * The pointers presented to the host. Some hosts ignore the absolute pointer, e.g. iPadOS, Android, and games or
+ * VMs capturing the pointer.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MouseMode {
+    // Only the absolute pointer, relative moves are added to the position
+    #[default]
+    Absolute,
+    // Only the relative pointer, absolute moves are sent as the distance from the last position
+    Relative,
+    // Both pointers, each move goes to its own, the buttons and the wheel follow the last move
+    Auto,
+}
+
+impl FromStr for MouseMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "absolute" | "abs" | "" => Ok(Self::Absolute),
+            "relative" | "rel" => Ok(Self::Relative),
+            "auto" => Ok(Self::Auto),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for MouseMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MouseMode::Absolute => "absolute",
+            MouseMode::Relative => "relative",
+            MouseMode::Auto => "auto",
+        })
+    }
+}
+
+fn to_pixels(from: u16, to: u16, size: u16) -> i16 {
+    let delta = (to as i32 - from as i32) * size as i32;
+    // Rounded to the nearest pixel
+    ((delta + delta.signum() * ABS_MAX / 2) / ABS_MAX) as i16
+}
+
+fn to_absolute(from: u16, delta: i16, size: u16) -> u16 {
+    let delta = delta as i32 * ABS_MAX / size.max(1) as i32;
+    (from as i32 + delta).clamp(0, ABS_MAX) as u16
+}
+
+/**
+ * The distance in pixels between two absolute positions, on a screen of `screen` pixels.
+ */
+pub fn relative_move(from: (u16, u16), to: (u16, u16), screen: (u16, u16)) -> (i16, i16) {
+    (
+        to_pixels(from.0, to.0, screen.0),
+        to_pixels(from.1, to.1, screen.1),
+    )
+}
+
+/**
+ * The absolute position after moving `delta` pixels, kept on the screen.
+ */
+pub fn absolute_move(from: (u16, u16), delta: (i16, i16), screen: (u16, u16)) -> (u16, u16) {
+    (
+        to_absolute(from.0, delta.0, screen.0),
+        to_absolute(from.1, delta.1, screen.1),
+    )
+}
+
+/**
+ * Splits a move into the steps of a relative report, at most 127 pixels each way.
+ */
+pub fn split_move(x: i16, y: i16) -> Vec<(i8, i8)> {
+    let mut ret = vec![];
+    let (mut x, mut y) = (x as i32, y as i32);
+    while x != 0 || y != 0 {
+        let step_x = x.clamp(-127, 127);
+        let step_y = y.clamp(-127, 127);
+        ret.push((step_x as i8, step_y as i8));
+        x -= step_x;
+        y -= step_y;
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mouse_mode() {
+        for mode in [MouseMode::Absolute, MouseMode::Relative, MouseMode::Auto] {
+            assert_eq!(mode.to_string().parse(), Ok(mode));
+        }
+        assert_eq!(" Rel ".parse(), Ok(MouseMode::Relative));
+        assert_eq!("touch".parse::<MouseMode>(), Err(()));
+    }
+
+    #[test]
+    fn conversions() {
+        let screen = (1920, 1080);
+        // Half of the screen
+        assert_eq!(
+            relative_move((0, 0x7fff), (0x4000, 0x4000), screen),
+            (960, -540)
+        );
+        assert_eq!(relative_move((100, 100), (100, 100), screen), (0, 0));
+        assert_eq!(absolute_move((0, 0), (960, 540), screen), (16383, 16383));
+        // Kept on the screen
+        assert_eq!(
+            absolute_move((100, 0x7f00), (-10, 100), screen),
+            (0, 0x7fff)
+        );
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_move(0, 0), vec![]);
+        assert_eq!(split_move(5, -3), vec![(5, -3)]);
+        assert_eq!(split_move(300, -130), vec![(127, -127), (127, -3), (46, 0)]);
+    }
+}
@@ -0,0 +1,67 @@
+use thiserror::Error;
+
+/**
+ * The state of the USB device as reported by TinyUSB, in the order of the `USB_STATE_*` codes of `usb_util.c`.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UsbState {
+    #[default]
+    Unmounted = 0,
+    Mounted = 1,
+    // The host is asleep, the bus is idle
+    Suspended = 2,
+}
+
+impl UsbState {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            1 => UsbState::Mounted,
+            2 => UsbState::Suspended,
+            _ => UsbState::Unmounted,
+        }
+    }
+}
+
+/**
+ * Why a report didn't reach the send queue, from the return codes of the `usb_util_*_report` functions.
+ */
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportError {
+    #[error("USB is not mounted")]
+    NotMounted,
+    #[error("USB host is suspended")]
+    Suspended,
+    #[error("USB report queue is full")]
+    QueueFull,
+    #[error("unknown USB report error {0}")]
+    Unknown(i32),
+}
+
+impl ReportError {
+    pub fn check(code: i32) -> Result<(), Self> {
+        match code {
+            0 => Ok(()),
+            1 => Err(ReportError::NotMounted),
+            2 => Err(ReportError::Suspended),
+            3 => Err(ReportError::QueueFull),
+            _ => Err(ReportError::Unknown(code)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        assert_eq!(
+            UsbState::from_code(UsbState::Suspended as u8),
+            UsbState::Suspended
+        );
+        assert_eq!(UsbState::from_code(9), UsbState::Unmounted);
+        assert_eq!(ReportError::check(0), Ok(()));
+        assert_eq!(ReportError::check(2), Err(ReportError::Suspended));
+        assert_eq!(ReportError::check(-1), Err(ReportError::Unknown(-1)));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{cmp::Reverse, fmt::Display, net::Ipv4Addr, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedNetwork<'a> {
+    pub ssid: &'a str,
+    pub password: &'a str,
+    // Higher goes first
+    pub priority: u16,
+    // 802.1X with the shared enterprise credentials instead of the password
+    pub enterprise: bool,
+}
+
+/**
+ * The inner authentication of EAP-TTLS, PEAP always uses MSCHAPv2.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TtlsPhase2 {
+    #[default]
+    MsChapV2,
+    MsChap,
+    Pap,
+    Chap,
+}
+
+impl FromStr for TtlsPhase2 {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "mschapv2" | "" => Ok(Self::MsChapV2),
+            "mschap" => Ok(Self::MsChap),
+            "pap" => Ok(Self::Pap),
+            "chap" => Ok(Self::Chap),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for TtlsPhase2 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TtlsPhase2::MsChapV2 => "mschapv2",
+            TtlsPhase2::MsChap => "mschap",
+            TtlsPhase2::Pap => "pap",
+            TtlsPhase2::Chap => "chap",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedAp<'a> {
+    pub ssid: &'a str,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    pub rssi: i8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    // Index into the saved networks
+    pub network: usize,
+    // The strongest access point of the network, `None` if it was not seen in the scan
+    pub bssid: Option<[u8; 6]>,
+    pub channel: Option<u8>,
+}
+
+/**
+ * Orders the saved networks to try, the ones seen in the scan go first, by priority then by signal strength, each
+ * with its strongest access point. The rest follow by priority as they may be hidden networks.
+ */
+pub fn rank_networks(saved: &[SavedNetwork], scan: &[ScannedAp]) -> Vec<Candidate> {
+    let mut visible = vec![];
+    let mut hidden = vec![];
+    for (index, network) in saved.iter().enumerate() {
+        let best = scan
+            .iter()
+            .filter(|ap| ap.ssid == network.ssid)
+            .max_by_key(|ap| ap.rssi);
+        match best {
+            Some(ap) => visible.push((
+                network.priority,
+                ap.rssi,
+                Candidate {
+                    network: index,
+                    bssid: Some(ap.bssid),
+                    channel: Some(ap.channel),
+                },
+            )),
+            None => hidden.push((
+                network.priority,
+                Candidate {
+                    network: index,
+                    bssid: None,
+                    channel: None,
+                },
+            )),
+        }
+    }
+    // Stable sorts, so the order in the settings breaks the ties
+    visible.sort_by_key(|(priority, rssi, _)| Reverse((*priority, *rssi)));
+    hidden.sort_by_key(|(priority, _)| Reverse(*priority));
+    visible
+        .into_iter()
+        .map(|(_, _, c)| c)
+        .chain(hidden.into_iter().map(|(_, c)| c))
+        .collect()
+}
+
+/**
+ * Turns the screen name into a DHCP hostname, letters, digits and hyphens only, lower case, at most `max_len` long.
+ */
+pub fn hostname_from(name: &str, max_len: usize) -> String {
+    let mut ret = String::new();
+    for c in name.chars() {
+        if ret.len() >= max_len {
+            break;
+        }
+        if c.is_ascii_alphanumeric() {
+            ret.push(c.to_ascii_lowercase());
+        } else if !ret.is_empty() && !ret.ends_with('-') {
+            ret.push('-');
+        }
+    }
+    let ret = ret.trim_end_matches('-');
+    if ret.is_empty() {
+        "esparrier".to_owned()
+    } else {
+        ret.to_owned()
+    }
+}
+
+/**
+ * The prefix length of a netmask, `None` if the ones are not contiguous.
+ */
+pub fn netmask_prefix(mask: Ipv4Addr) -> Option<u8> {
+    let mask = u32::from(mask);
+    let prefix = mask.leading_ones();
+    if mask.checked_shl(prefix).unwrap_or(0) == 0 {
+        Some(prefix as u8)
+    } else {
+        None
+    }
+}
+
+// WPA2 passphrases are 8 to 63 characters
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+pub const MAX_PASSPHRASE_LEN: usize = 63;
+
+// No look-alikes such as 0/o or 1/l, the passphrase is read from the log or the screen
+const PASSPHRASE_CHARS: &[u8; 32] = b"abcdefghijkmnpqrstuvwxyz23456789";
+
+/**
+ * A passphrase with one character for each random byte, 32 characters divide 256 so none of them is more likely.
+ */
+pub fn passphrase_from(random: &[u8]) -> String {
+    random
+        .iter()
+        .map(|b| PASSPHRASE_CHARS[*b as usize % PASSPHRASE_CHARS.len()] as char)
+        .collect()
+}
+
+/**
+ * Whether the passphrase can be used for a WPA2 personal network.
+ */
+pub fn is_valid_passphrase(passphrase: &str) -> bool {
+    (MIN_PASSPHRASE_LEN..=MAX_PASSPHRASE_LEN).contains(&passphrase.len())
+        && passphrase.bytes().all(|b| (0x20..0x7f).contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(ssid: &str, priority: u16) -> SavedNetwork<'_> {
+        SavedNetwork {
+            ssid,
+            password: "password",
+            priority,
+            enterprise: false,
+        }
+    }
+
+    fn ap(ssid: &str, id: u8, rssi: i8) -> ScannedAp<'_> {
+        ScannedAp {
+            ssid,
+            bssid: [0, 0, 0, 0, 0, id],
+            channel: id,
+            rssi,
+        }
+    }
+
+    fn order(candidates: &[Candidate]) -> Vec<usize> {
+        candidates.iter().map(|c| c.network).collect()
+    }
+
+    #[test]
+    fn priority_then_rssi() {
+        let saved = [saved("home", 0), saved("office", 1), saved("lab", 1)];
+        let scan = [ap("home", 1, -30), ap("office", 2, -80), ap("lab", 3, -50)];
+        assert_eq!(order(&rank_networks(&saved, &scan)), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn strongest_ap() {
+        let saved = [saved("office", 0)];
+        let scan = [
+            ap("office", 1, -70),
+            ap("office", 2, -40),
+            ap("guest", 3, -20),
+        ];
+        assert_eq!(
+            rank_networks(&saved, &scan),
+            vec![Candidate {
+                network: 0,
+                bssid: Some([0, 0, 0, 0, 0, 2]),
+                channel: Some(2),
+            }]
+        );
+    }
+
+    #[test]
+    fn ttls_phase2() {
+        for phase2 in [
+            TtlsPhase2::MsChapV2,
+            TtlsPhase2::MsChap,
+            TtlsPhase2::Pap,
+            TtlsPhase2::Chap,
+        ] {
+            assert_eq!(phase2.to_string().parse(), Ok(phase2));
+        }
+        assert_eq!(" PAP ".parse(), Ok(TtlsPhase2::Pap));
+        assert_eq!("eap".parse::<TtlsPhase2>(), Err(()));
+    }
+
+    #[test]
+    fn hidden_last() {
+        let saved = [saved("hidden", 5), saved("home", 0), saved("other", 1)];
+        let scan = [ap("home", 1, -90)];
+        let ranked = rank_networks(&saved, &scan);
+        assert_eq!(order(&ranked), vec![1, 0, 2]);
+        assert_eq!(ranked[1].bssid, None);
+    }
+
+    #[test]
+    fn hostname() {
+        assert_eq!(hostname_from("ESPARRIER", 30), "esparrier");
+        assert_eq!(hostname_from("Lab Desk #2", 30), "lab-desk-2");
+        assert_eq!(hostname_from("__a__b__", 30), "a-b");
+        assert_eq!(hostname_from("abcdef-ghi", 7), "abcdef");
+        assert_eq!(hostname_from("显示器", 30), "esparrier");
+    }
+
+    #[test]
+    fn netmask() {
+        assert_eq!(netmask_prefix(Ipv4Addr::new(255, 255, 255, 0)), Some(24));
+        assert_eq!(netmask_prefix(Ipv4Addr::new(255, 255, 240, 0)), Some(20));
+        assert_eq!(netmask_prefix(Ipv4Addr::new(255, 255, 255, 255)), Some(32));
+        assert_eq!(netmask_prefix(Ipv4Addr::new(0, 0, 0, 0)), Some(0));
+        assert_eq!(netmask_prefix(Ipv4Addr::new(255, 0, 255, 0)), None);
+    }
+
+    #[test]
+    fn passphrase() {
+        assert_eq!(passphrase_from(&[0, 1, 31, 32, 255]), "ab9a9");
+        let random: Vec<u8> = (0..=255).collect();
+        let passphrase = passphrase_from(&random);
+        assert_eq!(passphrase.len(), 256);
+        assert!(!passphrase.contains(['0', 'o', '1', 'l']));
+        assert!(is_valid_passphrase(&passphrase[..12]));
+    }
+
+    #[test]
+    fn valid_passphrase() {
+        assert!(is_valid_passphrase("12345678"));
+        assert!(is_valid_passphrase(&"x".repeat(63)));
+        assert!(!is_valid_passphrase("1234567"));
+        assert!(!is_valid_passphrase(&"x".repeat(64)));
+        assert!(!is_valid_passphrase("密码密码密码"));
+    }
+}
@@ -0,0 +1,31 @@
+use std::{
+    net::{Ipv4Addr, UdpSocket},
+    thread,
+};
+
+use anyhow::Result;
+use esparrier_core::dns::captive_response;
+use log::{info, warn};
+
+/**
+ * Answers every DNS query with the address of the board, so phones and laptops joining the access point open the
+ * configuration page by themselves.
+ */
+pub fn start_captive_dns(ip: Ipv4Addr) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 53))?;
+    thread::Builder::new().stack_size(4096).spawn(move || {
+        let mut buf = [0; 512];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((n, addr)) => {
+                    if let Some(response) = captive_response(&buf[..n], ip.octets()) {
+                        socket.send_to(&response, addr).ok();
+                    }
+                }
+                Err(e) => warn!("Failed to receive DNS query: {}", e),
+            }
+        }
+    })?;
+    info!("Captive DNS started");
+    Ok(())
+}
@@ -0,0 +1,669 @@
+use std::{net::Ipv4Addr, thread, time::Duration};
+
+use anyhow::Result;
+use embedded_svc::{
+    http::{Headers, Method},
+    io::{Read, Write},
+};
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+use esparrier_core::{
+    barrier::Fingerprint,
+    form::{basic_auth, constant_time_eq, escape_html, parse_form, parse_hex_u16},
+    wifi::is_valid_passphrase,
+};
+use log::{info, warn};
+
+use crate::settings::*;
+
+// The form is small, anything larger is not from the page, the CA certificate takes most of it
+const MAX_BODY_SIZE: usize = 8192;
+// The limit of a string in NVS
+const MAX_CERTIFICATE_SIZE: usize = 4000;
+// Any user name is accepted, only the password is checked
+const AUTH_CHALLENGE: &str = "Basic realm=\"Esparrier\"";
+// The checkbox that erases a secret is named with this prefix and the key of the secret
+const CLEAR_PREFIX: &str = "clear_";
+// The hidden field that proves the form comes from the page served by this board
+const TOKEN_FIELD: &str = "_token";
+
+enum Kind {
+    Text,
+    // Erased if submitted empty
+    OptionalText,
+    // Not shown on the page, left unchanged if submitted empty, erased with the clear checkbox
+    Secret,
+    // A secret that must be a valid WPA2 passphrase
+    Passphrase,
+    // Erased if submitted empty
+    Fingerprint,
+    U16,
+    // Shown and entered in hex, e.g. `0x303a`
+    Hex16,
+    Bool,
+    Scale,
+    Choice(&'static [&'static str]),
+    // PEM in a text area, erased if submitted empty
+    Certificate,
+    // Erased if submitted empty
+    Ipv4,
+}
+
+struct Field {
+    key: &'static str,
+    label: &'static str,
+    kind: Kind,
+    value: fn() -> String,
+}
+
+fn ip_string(ip: Option<Ipv4Addr>) -> String {
+    ip.map(|ip| ip.to_string()).unwrap_or_default()
+}
+
+fn slot_ssid(slot: usize) -> String {
+    get_wifi_slot(slot)
+        .map(|n| n.ssid.to_owned())
+        .unwrap_or_default()
+}
+
+fn slot_enterprise(slot: usize) -> String {
+    get_wifi_slot(slot)
+        .map(|n| n.enterprise)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn slot_priority(slot: usize) -> String {
+    get_wifi_slot(slot)
+        .map(|n| n.priority)
+        .unwrap_or_default()
+        .to_string()
+}
+
+const FIELDS: &[Field] = &[
+    Field {
+        key: "ssid",
+        label: "WiFi SSID",
+        kind: Kind::Text,
+        value: || get_wifi_ssid().to_owned(),
+    },
+    Field {
+        key: "pass",
+        label: "WiFi password",
+        kind: Kind::Secret,
+        value: String::new,
+    },
+    Field {
+        key: "prio",
+        label: "WiFi priority",
+        kind: Kind::U16,
+        value: || slot_priority(0),
+    },
+    Field {
+        key: "eap",
+        label: "WiFi enterprise (802.1X)",
+        kind: Kind::Bool,
+        value: || slot_enterprise(0),
+    },
+    Field {
+        key: "ssid1",
+        label: "WiFi SSID 2",
+        kind: Kind::OptionalText,
+        value: || slot_ssid(1),
+    },
+    Field {
+        key: "pass1",
+        label: "WiFi password 2",
+        kind: Kind::Secret,
+        value: String::new,
+    },
+    Field {
+        key: "prio1",
+        label: "WiFi priority 2",
+        kind: Kind::U16,
+        value: || slot_priority(1),
+    },
+    Field {
+        key: "eap1",
+        label: "WiFi enterprise 2",
+        kind: Kind::Bool,
+        value: || slot_enterprise(1),
+    },
+    Field {
+        key: "ssid2",
+        label: "WiFi SSID 3",
+        kind: Kind::OptionalText,
+        value: || slot_ssid(2),
+    },
+    Field {
+        key: "pass2",
+        label: "WiFi password 3",
+        kind: Kind::Secret,
+        value: String::new,
+    },
+    Field {
+        key: "prio2",
+        label: "WiFi priority 3",
+        kind: Kind::U16,
+        value: || slot_priority(2),
+    },
+    Field {
+        key: "eap2",
+        label: "WiFi enterprise 3",
+        kind: Kind::Bool,
+        value: || slot_enterprise(2),
+    },
+    Field {
+        key: "ssid3",
+        label: "WiFi SSID 4",
+        kind: Kind::OptionalText,
+        value: || slot_ssid(3),
+    },
+    Field {
+        key: "pass3",
+        label: "WiFi password 4",
+        kind: Kind::Secret,
+        value: String::new,
+    },
+    Field {
+        key: "prio3",
+        label: "WiFi priority 4",
+        kind: Kind::U16,
+        value: || slot_priority(3),
+    },
+    Field {
+        key: "eap3",
+        label: "WiFi enterprise 4",
+        kind: Kind::Bool,
+        value: || slot_enterprise(3),
+    },
+    Field {
+        key: "eap_identity",
+        label: "Enterprise identity",
+        kind: Kind::OptionalText,
+        value: || get_eap_identity().unwrap_or_default().to_owned(),
+    },
+    Field {
+        key: "eap_user",
+        label: "Enterprise username",
+        kind: Kind::OptionalText,
+        value: || get_eap_username().unwrap_or_default().to_owned(),
+    },
+    Field {
+        key: "eap_pass",
+        label: "Enterprise password",
+        kind: Kind::Secret,
+        value: String::new,
+    },
+    Field {
+        key: "eap_phase2",
+        label: "EAP-TTLS inner method",
+        kind: Kind::Choice(&["mschapv2", "mschap", "pap", "chap"]),
+        value: || get_eap_ttls_phase2().to_string(),
+    },
+    Field {
+        key: "eap_ca",
+        label: "Enterprise CA certificate (PEM)",
+        kind: Kind::Certificate,
+        value: || {
+            get_eap_ca_cert()
+                .map(|c| c.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        },
+    },
+    Field {
+        key: "wifi_retries",
+        label: "WiFi attempts before setup mode",
+        kind: Kind::U16,
+        value: || get_wifi_retries().to_string(),
+    },
+    Field {
+        key: "ap_pass",
+        label: "Setup network password",
+        kind: Kind::Passphrase,
+        value: String::new,
+    },
+    Field {
+        key: "static_ip",
+        label: "Static IP (empty for DHCP)",
+        kind: Kind::Ipv4,
+        value: || ip_string(get_static_ip()),
+    },
+    Field {
+        key: "netmask",
+        label: "Netmask",
+        kind: Kind::Ipv4,
+        value: || get_netmask().to_string(),
+    },
+    Field {
+        key: "gateway",
+        label: "Gateway",
+        kind: Kind::Ipv4,
+        value: || ip_string(get_gateway()),
+    },
+    Field {
+        key: "dns",
+        label: "DNS server",
+        kind: Kind::Ipv4,
+        value: || ip_string(get_dns()),
+    },
+    Field {
+        key: "dns2",
+        label: "Secondary DNS server",
+        kind: Kind::Ipv4,
+        value: || ip_string(get_dns2()),
+    },
+    Field {
+        key: "barrier_server",
+        label: "Barrier server (empty to discover)",
+        kind: Kind::OptionalText,
+        value: || get_barrier_server().to_owned(),
+    },
+    Field {
+        key: "barrier_port",
+        label: "Barrier port",
+        kind: Kind::U16,
+        value: || get_barrier_port().to_string(),
+    },
+    Field {
+        key: "barrier_tls",
+        label: "Use TLS",
+        kind: Kind::Bool,
+        value: || get_barrier_tls().to_string(),
+    },
+    Field {
+        key: "barrier_fp",
+        label: "Server fingerprint",
+        kind: Kind::Fingerprint,
+        value: || get_barrier_fingerprint().unwrap_or_default().to_owned(),
+    },
+    Field {
+        key: "screen_name",
+        label: "Screen name",
+        kind: Kind::Text,
+        value: || get_screen_name().to_owned(),
+    },
+    Field {
+        key: "screen_width",
+        label: "Screen width",
+        kind: Kind::U16,
+        value: || get_screen_width().to_string(),
+    },
+    Field {
+        key: "screen_height",
+        label: "Screen height",
+        kind: Kind::U16,
+        value: || get_screen_height().to_string(),
+    },
+    Field {
+        key: "reversed_wheel",
+        label: "Reverse mouse wheel",
+        kind: Kind::Bool,
+        value: || get_reversed_wheel().to_string(),
+    },
+    Field {
+        key: "v_scroll_scale",
+        label: "Vertical scroll scale",
+        kind: Kind::Scale,
+        value: || get_v_scroll_scale().to_string(),
+    },
+    Field {
+        key: "h_scroll_scale",
+        label: "Horizontal scroll scale",
+        kind: Kind::Scale,
+        value: || get_h_scroll_scale().to_string(),
+    },
+    Field {
+        key: "mouse_mode",
+        label: "Mouse mode",
+        kind: Kind::Choice(&["absolute", "relative", "auto"]),
+        value: || get_mouse_mode().to_string(),
+    },
+    Field {
+        key: "remote_wakeup",
+        label: "Wake up the computer",
+        kind: Kind::Bool,
+        value: || get_remote_wakeup().to_string(),
+    },
+    Field {
+        key: "usb_vid",
+        label: "USB vendor ID",
+        kind: Kind::Hex16,
+        value: || format!("{:#06x}", get_usb_vid()),
+    },
+    Field {
+        key: "usb_pid",
+        label: "USB product ID",
+        kind: Kind::Hex16,
+        value: || format!("{:#06x}", get_usb_pid()),
+    },
+    Field {
+        key: "usb_mfr",
+        label: "USB manufacturer",
+        kind: Kind::Text,
+        value: || get_usb_manufacturer().to_owned(),
+    },
+    Field {
+        key: "usb_product",
+        label: "USB product",
+        kind: Kind::Text,
+        value: || get_usb_product().to_owned(),
+    },
+    Field {
+        key: "usb_serial",
+        label: "USB serial number",
+        kind: Kind::OptionalText,
+        value: || get_usb_serial().unwrap_or_default().to_owned(),
+    },
+    Field {
+        key: "keyboard_layout",
+        label: "Keyboard layout",
+        kind: Kind::Choice(&["us", "uk", "de", "fr", "dvorak", "jis"]),
+        value: || get_keyboard_layout().to_string(),
+    },
+    Field {
+        key: "unicode_input",
+        label: "Unicode input",
+        kind: Kind::Choice(&["skip", "windows", "linux", "macos"]),
+        value: || get_unicode_input().to_string(),
+    },
+    Field {
+        key: "paste_delay",
+        label: "Paste key delay (ms)",
+        kind: Kind::U16,
+        value: || get_paste_key_delay().to_string(),
+    },
+    Field {
+        key: "paste_nl_delay",
+        label: "Paste newline delay (ms)",
+        kind: Kind::U16,
+        value: || get_paste_newline_delay().to_string(),
+    },
+    Field {
+        key: "config_portal",
+        label: "Enable this page",
+        kind: Kind::Bool,
+        value: || get_config_portal().to_string(),
+    },
+    Field {
+        key: "portal_pass",
+        label: "Page password (required on the WiFi network)",
+        kind: Kind::Secret,
+        value: String::new,
+    },
+];
+
+fn render_page(message: Option<&str>, token: &str) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width\"><title>Esparrier</title></head>\
+         <body><h1>Esparrier</h1>",
+    );
+    if let Some(message) = message {
+        html += &format!("<p><b>{}</b></p>", escape_html(message));
+    }
+    html += &format!(
+        "<form method=\"post\"><input type=\"hidden\" name=\"{}\" value=\"{}\"><table>",
+        TOKEN_FIELD, token
+    );
+    for field in FIELDS {
+        let value = escape_html(&(field.value)());
+        let input = match field.kind {
+            Kind::Text | Kind::OptionalText | Kind::Fingerprint | Kind::Ipv4 => {
+                format!("<input name=\"{}\" value=\"{}\">", field.key, value)
+            }
+            Kind::Secret | Kind::Passphrase => format!(
+                "<input type=\"password\" name=\"{0}\" placeholder=\"unchanged\"> \
+                 <label><input type=\"checkbox\" name=\"{1}{0}\" value=\"1\">Clear</label>",
+                field.key, CLEAR_PREFIX
+            ),
+            Kind::U16 => format!(
+                "<input type=\"number\" min=\"0\" max=\"65535\" name=\"{}\" value=\"{}\">",
+                field.key, value
+            ),
+            Kind::Hex16 => format!(
+                "<input name=\"{}\" value=\"{}\" pattern=\"(0[xX])?[0-9a-fA-F]{{1,4}}\">",
+                field.key, value
+            ),
+            Kind::Scale => format!(
+                "<input type=\"number\" min=\"0\" max=\"655\" step=\"0.01\" name=\"{}\" value=\"{}\">",
+                field.key, value
+            ),
+            // Unchecked checkboxes are not submitted, the hidden input submits 0 for them
+            Kind::Bool => format!(
+                "<input type=\"hidden\" name=\"{0}\" value=\"0\">\
+                 <input type=\"checkbox\" name=\"{0}\" value=\"1\"{1}>",
+                field.key,
+                if value == "true" { " checked" } else { "" }
+            ),
+            Kind::Certificate => format!(
+                "<textarea name=\"{}\" rows=\"6\" cols=\"64\">{}</textarea>",
+                field.key, value
+            ),
+            Kind::Choice(choices) => {
+                let options: String = choices
+                    .iter()
+                    .map(|c| {
+                        let selected = if *c == value { " selected" } else { "" };
+                        format!("<option{}>{}</option>", selected, c)
+                    })
+                    .collect();
+                format!("<select name=\"{}\">{}</select>", field.key, options)
+            }
+        };
+        html += &format!("<tr><td>{}</td><td>{}</td></tr>", field.label, input);
+    }
+    html += "</table><p><button type=\"submit\">Save and reboot</button></p></form></body></html>";
+    html
+}
+
+fn save(form: &[(String, String)]) -> Result<(), String> {
+    // The last one wins, a checked checkbox comes after its hidden input
+    let get = |key: &str| {
+        form.iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.trim())
+    };
+    let cleared = |field: &Field| match field.kind {
+        Kind::Secret | Kind::Passphrase => {
+            get(&format!("{}{}", CLEAR_PREFIX, field.key)) == Some("1")
+                // Erasing a saved network also erases its password, e.g. `pass1` with `ssid1`
+                || field
+                    .key
+                    .strip_prefix("pass")
+                    .is_some_and(|slot| get(&format!("ssid{}", slot)) == Some(""))
+        }
+        _ => false,
+    };
+    // Validate everything before writing anything
+    for field in FIELDS {
+        let Some(value) = get(field.key) else {
+            continue;
+        };
+        if cleared(field) {
+            continue;
+        }
+        let valid = match field.kind {
+            Kind::Text => !value.is_empty(),
+            Kind::OptionalText | Kind::Secret => true,
+            Kind::Bool => value == "0" || value == "1",
+            Kind::Passphrase => value.is_empty() || is_valid_passphrase(value),
+            Kind::Fingerprint => value.is_empty() || value.parse::<Fingerprint>().is_ok(),
+            Kind::U16 => value.parse::<u16>().is_ok(),
+            Kind::Hex16 => parse_hex_u16(value).is_some(),
+            Kind::Scale => value
+                .parse::<f32>()
+                .map(|v| (0.0..=655.0).contains(&v))
+                .unwrap_or(false),
+            Kind::Choice(choices) => choices.contains(&value),
+            Kind::Ipv4 => value.is_empty() || value.parse::<Ipv4Addr>().is_ok(),
+            Kind::Certificate => {
+                value.is_empty()
+                    || (value.starts_with("-----BEGIN CERTIFICATE-----")
+                        && value.len() <= MAX_CERTIFICATE_SIZE)
+            }
+        };
+        if !valid {
+            return Err(format!("Invalid value for {}", field.label));
+        }
+    }
+
+    let mut writer = SettingsWriter::open().map_err(|e| e.to_string())?;
+    for field in FIELDS {
+        let value = get(field.key);
+        let ret = match (&field.kind, value) {
+            _ if cleared(field) => writer.erase(field.key),
+            // Fields missing from the form are left unchanged
+            (_, None) => Ok(()),
+            (Kind::Bool, Some(value)) => writer.set_bool(field.key, value == "1"),
+            (Kind::Secret | Kind::Passphrase, Some("")) => Ok(()),
+            (Kind::OptionalText | Kind::Fingerprint | Kind::Certificate | Kind::Ipv4, Some("")) => {
+                writer.erase(field.key)
+            }
+            // Text areas submit CRLF
+            (Kind::Certificate, Some(value)) => {
+                writer.set_str(field.key, &value.replace("\r\n", "\n"))
+            }
+            (Kind::U16, Some(value)) => {
+                writer.set_u16(field.key, value.parse().unwrap_or_default())
+            }
+            (Kind::Hex16, Some(value)) => {
+                writer.set_u16(field.key, parse_hex_u16(value).unwrap_or_default())
+            }
+            (Kind::Scale, Some(value)) => {
+                writer.set_f32(field.key, value.parse().unwrap_or_default())
+            }
+            (_, Some(value)) => writer.set_str(field.key, value),
+        };
+        ret.map_err(|e| format!("Failed to save {}: {}", field.label, e))?;
+    }
+    writer.commit().map_err(|e| e.to_string())
+}
+
+fn authorized(req: &impl Headers, password: &str) -> bool {
+    req.header("Authorization")
+        .and_then(basic_auth)
+        .map(|(_, p)| constant_time_eq(p.as_bytes(), password.as_bytes()))
+        .unwrap_or(false)
+}
+
+// Changes on every boot, so a form can't be prepared in advance by another page
+fn session_token() -> String {
+    let mut random = [0u8; 16];
+    unsafe { esp_idf_sys::esp_fill_random(random.as_mut_ptr() as *mut _, random.len()) };
+    random.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/**
+ * Serves the configuration page on port 80, the settings are saved into NVS and the board reboots to apply them.
+ * With `captive` set to the address of the access point, every other page redirects to the configuration page.
+ * Every page asks for the password of `get_portal_password`, on the setup network it defaults to the password of
+ * the network. On the WiFi network the page needs its own password, and isn't served without one.
+ */
+pub fn start_config_portal(captive: Option<Ipv4Addr>) -> Result<Option<EspHttpServer>> {
+    let password = match (get_portal_password(), captive) {
+        (Some(password), _) => password,
+        // Whoever joined the setup network already knows its password
+        (None, Some(_)) => {
+            info!("Configuration page password is the setup network password");
+            get_ap_password()
+        }
+        // Basic authentication sends the password with every request over the shared network
+        (None, None) => {
+            warn!("Configuration page not started, it needs a password on the WiFi network");
+            return Ok(None);
+        }
+    };
+    let token = session_token();
+
+    let mut server = EspHttpServer::new(&Configuration {
+        uri_match_wildcard: captive.is_some(),
+        ..Default::default()
+    })?;
+
+    let page_token = token.clone();
+    server.fn_handler("/", Method::Get, move |req| {
+        if !authorized(&req, password) {
+            req.into_response(
+                401,
+                Some("Unauthorized"),
+                &[("WWW-Authenticate", AUTH_CHALLENGE)],
+            )?
+            .write_all(b"Unauthorized")?;
+            return Ok(());
+        }
+        req.into_ok_response()?
+            .write_all(render_page(None, &page_token).as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler("/", Method::Post, move |mut req| {
+        if !authorized(&req, password) {
+            req.into_response(
+                401,
+                Some("Unauthorized"),
+                &[("WWW-Authenticate", AUTH_CHALLENGE)],
+            )?
+            .write_all(b"Unauthorized")?;
+            return Ok(());
+        }
+        let mut body = Vec::new();
+        let mut buf = [0; 256];
+        loop {
+            let n = req.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            if body.len() + n > MAX_BODY_SIZE {
+                req.into_status_response(413)?
+                    .write_all(b"Request too large")?;
+                return Ok(());
+            }
+            body.extend_from_slice(&buf[..n]);
+        }
+
+        let form = parse_form(&body);
+        let valid_token = form
+            .iter()
+            .find(|(k, _)| k == TOKEN_FIELD)
+            .map(|(_, v)| constant_time_eq(v.as_bytes(), token.as_bytes()))
+            .unwrap_or(false);
+        if !valid_token {
+            warn!("Rejected settings without a valid session token");
+            req.into_status_response(403)?
+                .write_all(b"The page has expired, reload it and try again")?;
+            return Ok(());
+        }
+
+        match save(&form) {
+            Ok(_) => {
+                info!("Settings saved, rebooting...");
+                req.into_ok_response()?
+                    .write_all(b"Settings saved, rebooting...")?;
+                // Give the response some time to go out
+                thread::spawn(|| {
+                    thread::sleep(Duration::from_secs(1));
+                    unsafe { esp_idf_sys::esp_restart() };
+                });
+            }
+            Err(e) => {
+                warn!("Failed to save settings: {}", e);
+                req.into_status_response(400)?
+                    .write_all(render_page(Some(&e), &token).as_bytes())?;
+            }
+        }
+        Ok(())
+    })?;
+
+    // Registered last, so it doesn't shadow the handlers above
+    if let Some(ip) = captive {
+        let location = format!("http://{}/", ip);
+        server.fn_handler("/*", Method::Get, move |req| {
+            req.into_response(302, Some("Found"), &[("Location", location.as_str())])?;
+            Ok(())
+        })?;
+    }
+
+    info!("Configuration portal started");
+    Ok(Some(server))
+}
@@ -0,0 +1,183 @@
+use std::{
+    net::{Shutdown, TcpStream},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    mdns::EspMdns,
+    netif::IpEvent,
+    wifi::{EspWifi, WifiEvent},
+};
+use esparrier_core::{
+    backoff::Backoff,
+    barrier::{self, Actuator, ConnectionError, Watchdog},
+};
+use log::{error, info, warn};
+
+use crate::{
+    discovery::resolve_server,
+    settings::{get_barrier_fingerprint, get_barrier_tls, get_screen_name, get_static_ip},
+    status::{set_status, Status},
+    tls_stream::{TlsConfig, TlsStream},
+    utils::ensure_wifi,
+};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// A session lasting longer than this was not a failed attempt, the backoff starts over
+const STABLE_SESSION: Duration = Duration::from_secs(30);
+// Rejected by the server, nothing changes until someone fixes the server configuration
+const REJECTED_DELAY: Duration = Duration::from_secs(30);
+
+enum NetEvent {
+    Up,
+    Down,
+}
+
+fn connect<A: Actuator, W: Watchdog>(
+    server: &str,
+    port: u16,
+    tls: Option<&TlsConfig>,
+    socket: &Mutex<Option<TcpStream>>,
+    actor: &mut A,
+    watchdog: &mut W,
+) -> Result<(), ConnectionError> {
+    let stream = barrier::connect(server, port)?;
+    *socket.lock().unwrap() = stream.try_clone().ok();
+    let ret = match tls {
+        Some(config) => barrier::start(
+            TlsStream::connect(stream, server, config)?,
+            get_screen_name(),
+            actor,
+            watchdog,
+        ),
+        None => barrier::start(stream, get_screen_name(), actor, watchdog),
+    };
+    *socket.lock().unwrap() = None;
+    ret
+}
+
+/**
+ * Sleeps for `delay` while feeding the watchdog, returns early if the WiFi goes up or down.
+ */
+fn wait<W: Watchdog>(events: &Receiver<NetEvent>, delay: Duration, watchdog: &mut W) {
+    let start = Instant::now();
+    while let Some(left) = delay.checked_sub(start.elapsed()) {
+        watchdog.feed();
+        match events.recv_timeout(left.min(Duration::from_secs(1))) {
+            Ok(NetEvent::Up) => {
+                info!("Wifi is up, reconnecting now");
+                return;
+            }
+            Ok(NetEvent::Down) => {
+                info!("Wifi is down, reconnecting now");
+                return;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/**
+ * Keeps the board connected to the Barrier server, forever. Every failure, WiFi, discovery or the server itself,
+ * is retried with an exponential backoff, and losing the WiFi ends the session right away instead of waiting for
+ * the keep alive to time out.
+ */
+pub fn run<A: Actuator, W: Watchdog>(
+    wifi: &mut EspWifi<'static>,
+    sysloop: EspSystemEventLoop,
+    actor: &mut A,
+    watchdog: &mut W,
+) -> Result<()> {
+    let tls = if get_barrier_tls() {
+        Some(TlsConfig {
+            fingerprint: get_barrier_fingerprint().map(|fp| fp.parse()).transpose()?,
+        })
+    } else {
+        None
+    };
+    let mdns = EspMdns::take()?;
+
+    // The socket of the current session, so the event handler can shut it down
+    let socket: Arc<Mutex<Option<TcpStream>>> = Arc::new(Mutex::new(None));
+    let (tx, events) = channel();
+    // With a static IP there is no DHCP, the address is usable as soon as the station is connected
+    let static_ip = get_static_ip().is_some();
+    let _wifi_subscription = {
+        let socket = socket.clone();
+        let tx = tx.clone();
+        sysloop.subscribe::<WifiEvent, _>(move |event| match event {
+            WifiEvent::StaDisconnected => {
+                if let Some(socket) = socket.lock().unwrap().as_ref() {
+                    socket.shutdown(Shutdown::Both).ok();
+                }
+                tx.send(NetEvent::Down).ok();
+            }
+            WifiEvent::StaConnected if static_ip => {
+                tx.send(NetEvent::Up).ok();
+            }
+            _ => {}
+        })?
+    };
+    let _ip_subscription = sysloop.subscribe::<IpEvent, _>(move |event| {
+        if let IpEvent::DhcpIpAssigned(_) = event {
+            tx.send(NetEvent::Up).ok();
+        }
+    })?;
+
+    let mut backoff = Backoff::new(MIN_BACKOFF, MAX_BACKOFF);
+    loop {
+        watchdog.feed();
+        // The board may have been moved away from the network
+        let ret = ensure_wifi(wifi, sysloop.clone(), watchdog)
+            .and_then(|_| resolve_server(&mdns))
+            .map(|(server, port)| {
+                info!("Connecting to barrier {}:{}...", server, port);
+                let start = Instant::now();
+                let ret = connect(&server, port, tls.as_ref(), &socket, actor, watchdog);
+                match &ret {
+                    Ok(_) => info!("Connection closed"),
+                    Err(e) => error!("Connection failed: {}", e),
+                }
+                (start.elapsed(), ret.err().filter(|e| e.is_rejected()))
+            });
+        // Events from the attempt above are stale, except losing the WiFi during the session, even if it's back
+        let mut wifi_lost = false;
+        while let Ok(event) = events.try_recv() {
+            wifi_lost |= matches!(event, NetEvent::Down);
+        }
+        let mut min_delay = Duration::ZERO;
+        match ret {
+            Ok((duration, _)) if wifi_lost => {
+                warn!("Wifi lost after {:?}, reconnecting now", duration);
+                backoff.reset();
+                continue;
+            }
+            Ok((_, Some(rejection))) => {
+                warn!("Rejected by the server, check the server configuration");
+                set_status(match rejection {
+                    ConnectionError::UnknownScreen => Status::UnknownScreen,
+                    ConnectionError::ScreenBusy => Status::ScreenBusy,
+                    _ => Status::Incompatible,
+                });
+                min_delay = REJECTED_DELAY;
+            }
+            Ok((duration, None)) if duration > STABLE_SESSION => backoff.reset(),
+            Ok(_) => {}
+            Err(e) => error!("Failed to reach the server: {}", e),
+        }
+        // After the reset above, so a long session doesn't wait the delay of the failures before it
+        let delay = backoff
+            .next_delay(unsafe { esp_idf_sys::esp_random() })
+            .max(min_delay);
+        info!("Reconnecting in {:?}...", delay);
+        wait(&events, delay, watchdog);
+    }
+}
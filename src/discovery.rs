@@ -0,0 +1,55 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::{anyhow, Result};
+use esp_idf_svc::mdns::{EspMdns, QueryResult};
+use esparrier_core::discovery::{ServerAddress, SERVICE_TYPES};
+use log::{info, warn};
+
+use crate::settings::{get_barrier_port, get_barrier_server};
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_RESULTS: usize = 4;
+
+/**
+ * Finds the address and the port of the Barrier server, browses DNS-SD if no server is configured, and resolves
+ * `.local` names with mDNS. The returned host is also used as the TLS server name.
+ */
+pub fn resolve_server(mdns: &EspMdns) -> Result<(String, u16)> {
+    match ServerAddress::parse(get_barrier_server()) {
+        ServerAddress::Host(host) => Ok((host.to_owned(), get_barrier_port())),
+        ServerAddress::Mdns(name) => {
+            let ip = mdns.query_a(name, QUERY_TIMEOUT)?;
+            info!("Resolved {}.local to {}", name, ip);
+            Ok((ip.to_string(), get_barrier_port()))
+        }
+        ServerAddress::Discover => {
+            for service in SERVICE_TYPES {
+                let mut results: [QueryResult; MAX_RESULTS] = Default::default();
+                let n =
+                    match mdns.query_ptr(service, "_tcp", QUERY_TIMEOUT, MAX_RESULTS, &mut results)
+                    {
+                        Ok(n) => n,
+                        Err(e) => {
+                            warn!("Failed to browse {}: {}", service, e);
+                            continue;
+                        }
+                    };
+                // The first server with an IPv4 address, the board has no IPv6
+                let found = results[..n.min(MAX_RESULTS)].iter().find_map(|r| {
+                    r.addr
+                        .iter()
+                        .find(|a| matches!(a, IpAddr::V4(_)))
+                        .map(|a| (r, *a))
+                });
+                if let Some((result, addr)) = found {
+                    info!(
+                        "Discovered server {:?} at {}:{}",
+                        result.instance_name, addr, result.port
+                    );
+                    return Ok((addr.to_string(), result.port));
+                }
+            }
+            Err(anyhow!("No Barrier server found"))
+        }
+    }
+}
@@ -6,16 +6,21 @@ use esp_idf_hal::prelude::Peripherals;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_sys::{self as _, nvs_flash_init};
 use lazy_static::lazy_static;
-use log::{error, info};
+use log::info;
 
-mod barrier;
-mod keycodes;
+mod captive_dns;
+mod config_portal;
+mod connection;
+mod discovery;
 mod reports;
 mod settings;
 mod status;
+mod tls_stream;
 mod usb_actor;
 mod utils;
 
+use esparrier_core::{barrier, keycodes};
+
 use settings::*;
 use utils::*;
 
@@ -27,6 +32,10 @@ use crate::{
 #[from_env("DEBUG_INIT_USB")]
 pub const INIT_USB: bool = true;
 
+// Reboot to retry the saved network if nobody configured the board in time
+#[from_env("PROVISIONING_TIMEOUT")]
+const PROVISIONING_TIMEOUT: u64 = 600;
+
 #[cfg(feature = "watchdog")]
 use std::time::Duration;
 #[cfg(feature = "watchdog")]
@@ -37,6 +46,17 @@ use esp_idf_hal::{task::watchdog::TWDTConfig, cpu::Core};
 #[from_env("WATCHDOG_TIMEOUT")]
 const WATCHDOG_TIMEOUT: u64 = 15;
 
+// The subscription lives in esp-idf-hal and the trait in esparrier-core, so it needs a local wrapper
+#[cfg(feature = "watchdog")]
+struct TaskWatchdog<'a>(esp_idf_hal::task::watchdog::WatchdogSubscription<'a>);
+
+#[cfg(feature = "watchdog")]
+impl<'a> barrier::Watchdog for TaskWatchdog<'a> {
+    fn feed(&mut self) {
+        self.0.feed().expect("Failed to feed watchdog");
+    }
+}
+
 #[cfg(feature = "paste")]
 mod paste_button;
 #[cfg(feature = "paste")]
@@ -81,7 +101,9 @@ fn main() -> Result<()> {
         },
     )?;
     #[cfg(feature = "watchdog")]
-    let mut watchdog = driver.watch_current_task()?;
+    let mut watchdog = TaskWatchdog(driver.watch_current_task()?);
+    #[cfg(not(feature = "watchdog"))]
+    let mut watchdog = ();
     
     #[cfg(feature = "m5atoms3")]
     {
@@ -128,11 +150,32 @@ fn main() -> Result<()> {
     let sysloop = EspSystemEventLoop::take()?;
 
     // Initialize WIFI
-    let _wifi = wifi(peripherals.modem, sysloop).unwrap();
+    let (mut wifi, mode) = wifi(peripherals.modem, sysloop.clone(), &mut watchdog).unwrap();
+
+    if let WifiMode::AccessPoint(ip) = mode {
+        set_status(Status::Provisioning);
+        status::show_message(&format!("{}\n{}", setup_ssid(), get_ap_password()));
+        // Always served here, otherwise there is no way to fix the settings
+        let _config_portal = config_portal::start_config_portal(Some(ip))?;
+        captive_dns::start_captive_dns(ip)?;
+        let start = std::time::Instant::now();
+        while start.elapsed().as_secs() < PROVISIONING_TIMEOUT {
+            barrier::Watchdog::feed(&mut watchdog);
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+        info!("Provisioning timed out, restarting...");
+        unsafe { esp_idf_sys::esp_restart() };
+    }
 
     // Blue when connected to wifi
     set_status(Status::WifiConnected);
 
+    let _config_portal = if get_config_portal() {
+        config_portal::start_config_portal(None)?
+    } else {
+        None
+    };
+
     let screen_width = get_screen_width();
     let screen_height = get_screen_height();
 
@@ -144,6 +187,7 @@ fn main() -> Result<()> {
         start_paste_button_task(
             unsafe { AnyInputPin::new(PASTE_BUTTON_PIN) },
             actor.get_sender(),
+            actor.get_input_counter(),
         );
         actor
     };
@@ -152,28 +196,6 @@ fn main() -> Result<()> {
     #[cfg(not(feature = "paste"))]
     let mut actor = UsbHidActuator::new(screen_width, screen_height);
 
-    info!("Connecting to barrier...");
-    for _ in 0..10 {
-        match barrier::start(
-            get_barrier_server(),
-            get_barrier_port(),
-            get_screen_name(),
-            &mut actor,
-            #[cfg(feature = "watchdog")]
-            &mut watchdog,
-        ) {
-            Ok(_) => {
-                error!("Connection closed");
-            }
-            Err(e) => {
-                error!("Connection failed: {}", e);
-            }
-        }
-        info!("Reconnecting in 1 seconds...");
-        std::thread::sleep(std::time::Duration::from_secs(1));
-    }
-    info!("Failed to connect to barrier, restarting...");
-    set_status(Status::WifiConnected); // set_led(RGB { r: 0, g: 0, b: 255 });
-
-    panic!("Disconnected, restarting...")
+    // Never returns unless something is badly wrong, a server going offline is retried forever
+    connection::run(&mut wifi, sysloop, &mut actor, &mut watchdog)
 }
@@ -0,0 +1,121 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc,
+    },
+    thread,
+};
+
+use esparrier_core::{
+    layout::KeyboardLayout,
+    paste::{KeyEvent, Paste, PasteTiming, UnicodeInput},
+};
+use log::{info, warn};
+
+use crate::barrier::ActMsg;
+
+/**
+ * Types the clipboard in its own thread, so the button task stays responsive and the paste can be cancelled.
+ */
+pub struct PasteEngine {
+    req: SyncSender<Vec<u8>>,
+    busy: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+}
+
+struct Worker {
+    tx: SyncSender<ActMsg>,
+    input_counter: Arc<AtomicUsize>,
+    busy: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+    layout: KeyboardLayout,
+    unicode_input: UnicodeInput,
+    timing: PasteTiming,
+}
+
+impl PasteEngine {
+    pub fn start(
+        tx: SyncSender<ActMsg>,
+        input_counter: Arc<AtomicUsize>,
+        layout: KeyboardLayout,
+        unicode_input: UnicodeInput,
+        timing: PasteTiming,
+    ) -> Self {
+        let (req, rx) = sync_channel(1);
+        let busy = Arc::new(AtomicBool::new(false));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let worker = Worker {
+            tx,
+            input_counter,
+            busy: busy.clone(),
+            cancel: cancel.clone(),
+            layout,
+            unicode_input,
+            timing,
+        };
+        thread::Builder::new()
+            .stack_size(8192)
+            .spawn(move || worker.run(rx))
+            .expect("Failed to create paste thread");
+        Self { req, busy, cancel }
+    }
+
+    /**
+     * Starts pasting the text, or cancels the paste in progress.
+     */
+    pub fn toggle(&self, text: impl FnOnce() -> Vec<u8>) {
+        if self.busy.load(Ordering::Acquire) {
+            info!("Cancelling paste");
+            self.cancel.store(true, Ordering::Release);
+        } else {
+            self.cancel.store(false, Ordering::Release);
+            self.busy.store(true, Ordering::Release);
+            if self.req.try_send(text()).is_err() {
+                self.busy.store(false, Ordering::Release);
+            }
+        }
+    }
+}
+
+impl Worker {
+    fn run(self, rx: Receiver<Vec<u8>>) {
+        while let Ok(text) = rx.recv() {
+            self.paste(&text);
+            self.busy.store(false, Ordering::Release);
+        }
+    }
+
+    fn paste(&self, text: &[u8]) {
+        let inputs = self.input_counter.load(Ordering::Relaxed);
+        // Keys currently held down, released if the paste is aborted
+        let mut pressed: Vec<u8> = Vec::with_capacity(4);
+        for (event, delay) in Paste::new(text, self.layout, self.unicode_input, self.timing) {
+            if self.cancel.load(Ordering::Acquire)
+                || self.input_counter.load(Ordering::Relaxed) != inputs
+            {
+                warn!("Paste aborted");
+                for key in pressed.into_iter().rev() {
+                    self.tx.send(ActMsg::HidKeyUp { key }).ok();
+                }
+                return;
+            }
+            let msg = match event {
+                KeyEvent::Down(key) => {
+                    pressed.push(key);
+                    ActMsg::HidKeyDown { key }
+                }
+                KeyEvent::Up(key) => {
+                    pressed.retain(|k| *k != key);
+                    ActMsg::HidKeyUp { key }
+                }
+            };
+            if self.tx.send(msg).is_err() {
+                return;
+            }
+            if !delay.is_zero() {
+                thread::sleep(delay);
+            }
+        }
+    }
+}
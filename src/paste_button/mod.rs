@@ -1,41 +1,44 @@
-use std::sync::mpsc::SyncSender;
+use std::{
+    sync::{atomic::AtomicUsize, mpsc::SyncSender, Arc},
+    time::Duration,
+};
 
 use esp_idf_hal::gpio;
 
-use crate::{barrier::ActMsg, keycodes::ASCII_2_HID, CLIPBOARD};
+use esparrier_core::paste::PasteTiming;
+
+use crate::{
+    barrier::ActMsg,
+    settings::{
+        get_keyboard_layout, get_paste_key_delay, get_paste_newline_delay, get_unicode_input,
+    },
+    CLIPBOARD,
+};
 
 mod button;
+mod engine;
 
 use button::{ButtonCallback, ButtonState};
+use engine::PasteEngine;
 
 pub struct PasteButton {
-    tx: SyncSender<ActMsg>,
+    engine: PasteEngine,
 }
 
 impl PasteButton {
-    pub fn new(tx: SyncSender<ActMsg>) -> Self {
-        Self { tx }
-    }
-
-    /**
-     * Only supports 7bit ASCII characters as other keys don't have a USB HID code
-     */
-    fn send_char(&self, byte: u8) {
-        if byte > 0x7F {
-            return;
-        }
-        let [k, m] = ASCII_2_HID[byte as usize];
-        if k == 0 {
-            return;
-        }
-        if m != 0 {
-            self.tx.send(ActMsg::HidKeyDown { key: m }).ok();
-            self.tx.send(ActMsg::HidKeyDown { key: k }).ok();
-            self.tx.send(ActMsg::HidKeyUp { key: k }).ok();
-            self.tx.send(ActMsg::HidKeyUp { key: m }).ok();
-        } else {
-            self.tx.send(ActMsg::HidKeyDown { key: k }).ok();
-            self.tx.send(ActMsg::HidKeyUp { key: k }).ok();
+    pub fn new(tx: SyncSender<ActMsg>, input_counter: Arc<AtomicUsize>) -> Self {
+        let timing = PasteTiming {
+            key_delay: Duration::from_millis(get_paste_key_delay() as u64),
+            newline_delay: Duration::from_millis(get_paste_newline_delay() as u64),
+        };
+        Self {
+            engine: PasteEngine::start(
+                tx,
+                input_counter,
+                get_keyboard_layout(),
+                get_unicode_input(),
+                timing,
+            ),
         }
     }
 }
@@ -45,19 +48,18 @@ impl ButtonCallback for PasteButton {
         match state {
             ButtonState::Up => {}
             ButtonState::Down => {
-                let data = CLIPBOARD.lock().unwrap();
-                for c in data.iter() {
-                    if *c == 0 {
-                        break;
-                    }
-                    self.send_char(*c);
-                }
+                // A second press cancels the paste in progress
+                self.engine.toggle(|| CLIPBOARD.lock().unwrap().clone());
             }
             ButtonState::Held => {}
         }
     }
 }
 
-pub fn start_paste_button_task(pin: gpio::AnyInputPin, tx: SyncSender<ActMsg>) {
-    button::start_button_task(pin, PasteButton::new(tx));
+pub fn start_paste_button_task(
+    pin: gpio::AnyInputPin,
+    tx: SyncSender<ActMsg>,
+    input_counter: Arc<AtomicUsize>,
+) {
+    button::start_button_task(pin, PasteButton::new(tx, input_counter));
 }
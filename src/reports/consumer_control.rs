@@ -1,3 +1,5 @@
+use esparrier_core::reports::ReportError;
+
 use super::usb_util_consumer_report;
 
 pub struct ConsumerControlReport {
@@ -9,24 +11,22 @@ impl ConsumerControlReport {
         Self { code: 0 }
     }
 
-    pub fn press(&mut self, code: u16) {
+    pub fn press(&mut self, code: u16) -> Result<(), ReportError> {
         self.code = code;
-        self.send();
+        self.send()
     }
 
-    pub fn release(&mut self) {
+    pub fn release(&mut self) -> Result<(), ReportError> {
         self.code = 0;
-        self.send();
+        self.send()
     }
 
-    pub fn clear(&mut self) {
+    pub fn clear(&mut self) -> Result<(), ReportError> {
         self.code = 0;
-        self.send();
+        self.send()
     }
 
-    fn send(&self) {
-        unsafe {
-            usb_util_consumer_report(self.code);
-        }
+    fn send(&self) -> Result<(), ReportError> {
+        ReportError::check(unsafe { usb_util_consumer_report(self.code) })
     }
 }
\ No newline at end of file
@@ -1,26 +1,109 @@
-use log::warn;
+use std::{
+    ffi::{c_char, CString},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Mutex,
+    },
+};
+
+use log::{info, warn};
 
 mod abs_mouse;
 mod consumer_control;
 mod keyboard;
+mod rel_mouse;
 
 extern "C" {
-    fn usb_util_init();
-    fn usb_util_keyboard_report(modifier: u8, keycode: *const u8);
-    fn usb_util_abs_mouse_report(buttons: u8, x: u16, y: u16, wheel: i8, pan: i8);
-    fn usb_util_consumer_report(code: u16);
+    fn usb_util_init(mouse_mode: u8, remote_wakeup: bool, identity: *const RawUsbIdentity);
+    fn usb_util_keyboard_report(modifier: u8, keycode: *const u8) -> i32;
+    fn usb_util_abs_mouse_report(buttons: u8, x: u16, y: u16, wheel: i8, pan: i8) -> i32;
+    fn usb_util_rel_mouse_report(buttons: u8, x: i8, y: i8, wheel: i8, pan: i8) -> i32;
+    fn usb_util_consumer_report(code: u16) -> i32;
+    fn usb_util_remote_wakeup() -> bool;
 }
 
 use abs_mouse::AbsMouseReport;
 use consumer_control::ConsumerControlReport;
+use esparrier_core::reports::{absolute_move, relative_move, MouseMode, ReportError, UsbState};
 use keyboard::KeyboardReport;
+use rel_mouse::RelMouseReport;
 
 use crate::keycodes::KeyCode;
 
+/**
+ * The identity of the board on USB, TinyUSB sends at most 31 characters of each string.
+ */
+pub struct UsbIdentity {
+    pub vid: u16,
+    pub pid: u16,
+    pub manufacturer: String,
+    pub product: String,
+    pub serial: String,
+}
+
+// `usb_util_identity_t` in usb_util.c
+#[repr(C)]
+struct RawUsbIdentity {
+    vid: u16,
+    pid: u16,
+    manufacturer: *const c_char,
+    product: *const c_char,
+    serial: *const c_char,
+}
+
+/**
+ * The serial number of the board, the factory MAC address in hex, unique for each chip.
+ */
+pub fn mac_serial() -> String {
+    let mut mac = [0u8; 6];
+    unsafe {
+        esp_idf_sys::esp_efuse_mac_get_default(mac.as_mut_ptr());
+    }
+    mac.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+// Where the keyboard LEDs from the host go, only the latest `HidReport` receives them
+static KEYBOARD_LEDS: Mutex<Option<SyncSender<u8>>> = Mutex::new(None);
+
+/**
+ * Called by TinyUSB when the host sets the keyboard LEDs, must not block.
+ */
+#[no_mangle]
+extern "C" fn usb_util_keyboard_leds_cb(leds: u8) {
+    if let Some(tx) = KEYBOARD_LEDS.lock().unwrap().as_ref() {
+        tx.try_send(leds).ok();
+    }
+}
+
+static USB_STATE: AtomicU8 = AtomicU8::new(UsbState::Unmounted as u8);
+
+/**
+ * Called by TinyUSB when the device is mounted, unmounted, suspended or resumed, must not block.
+ */
+#[no_mangle]
+extern "C" fn usb_util_state_cb(state: u8) {
+    let state = UsbState::from_code(state);
+    let last = UsbState::from_code(USB_STATE.swap(state as u8, Ordering::Relaxed));
+    match (last, state) {
+        (UsbState::Suspended, UsbState::Mounted) => info!("USB resumed"),
+        _ => info!("USB {state:?}"),
+    }
+}
+
+/**
+ * The USB state last reported by TinyUSB.
+ */
+pub fn usb_state() -> UsbState {
+    UsbState::from_code(USB_STATE.load(Ordering::Relaxed))
+}
+
 pub enum HidReportType {
     KeyPress { key_code: KeyCode },
     KeyRelease { key_code: KeyCode },
+    // In the absolute range [0, 0x7fff]
     MouseMove { x: u16, y: u16 },
+    // In pixels
     MouseMoveRelative { x: i16, y: i16 },
     MouseDown { button: u8 },
     MouseUp { button: u8 },
@@ -28,55 +111,161 @@ pub enum HidReportType {
 }
 
 pub struct HidReport {
+    // Also tracks the position for the relative pointer
     mouse: AbsMouseReport,
+    rel_mouse: RelMouseReport,
     keyboard: KeyboardReport<6>,
     consumer_control: ConsumerControlReport,
+    mouse_mode: MouseMode,
+    // Whether the configuration descriptor advertises the remote wakeup
+    remote_wakeup: bool,
+    identity: UsbIdentity,
+    // Screen size in pixels, to convert between the absolute and relative moves
+    screen: (u16, u16),
+    // The last move was relative, the buttons and the wheel go to the same pointer in the auto mode
+    relative: bool,
+    keyboard_leds: Receiver<u8>,
 }
 
 impl HidReport {
-    pub fn new() -> Self {
+    pub fn new(
+        mouse_mode: MouseMode,
+        remote_wakeup: bool,
+        identity: UsbIdentity,
+        width: u16,
+        height: u16,
+    ) -> Self {
+        let (tx, keyboard_leds) = sync_channel(8);
+        *KEYBOARD_LEDS.lock().unwrap() = Some(tx);
         Self {
             mouse: AbsMouseReport::new(),
+            rel_mouse: RelMouseReport::new(),
             keyboard: KeyboardReport::new(),
             consumer_control: ConsumerControlReport::new(),
+            mouse_mode,
+            remote_wakeup,
+            identity,
+            screen: (width, height),
+            relative: mouse_mode == MouseMode::Relative,
+            keyboard_leds,
         }
     }
 
+    /**
+     * The latest keyboard LEDs set by the host since the last call, `None` if unchanged.
+     */
+    pub fn poll_keyboard_leds(&mut self) -> Option<u8> {
+        self.keyboard_leds.try_iter().last()
+    }
+
     pub fn init(&mut self) {
         if crate::INIT_USB {
-            unsafe { usb_util_init() }
+            // NVS strings have no NUL inside
+            let manufacturer =
+                CString::new(self.identity.manufacturer.as_str()).unwrap_or_default();
+            let product = CString::new(self.identity.product.as_str()).unwrap_or_default();
+            let serial = CString::new(self.identity.serial.as_str()).unwrap_or_default();
+            let identity = RawUsbIdentity {
+                vid: self.identity.vid,
+                pid: self.identity.pid,
+                manufacturer: manufacturer.as_ptr(),
+                product: product.as_ptr(),
+                serial: serial.as_ptr(),
+            };
+            // The strings are copied
+            unsafe { usb_util_init(self.mouse_mode as u8, self.remote_wakeup, &identity) }
         } else {
             warn!("Skipping USB init")
         }
     }
 
+    /**
+     * Signals the suspended host to resume, false if it's disabled, the host is awake or didn't allow the wakeup.
+     * Returns right away, the reports sent in the next 2 seconds are queued and sent once the host resumes.
+     */
+    pub fn remote_wakeup(&mut self) -> bool {
+        self.remote_wakeup && crate::INIT_USB && unsafe { usb_util_remote_wakeup() }
+    }
+
     pub fn get_mouse_position(&self) -> (u16, u16) {
         self.mouse.get_position()
     }
 
-    pub fn send(&mut self, report: HidReportType) {
+    /**
+     * Queues the report, fails without blocking for long if the host doesn't take reports.
+     */
+    pub fn send(&mut self, report: HidReportType) -> Result<(), ReportError> {
         match report {
             HidReportType::KeyPress { key_code } => match key_code {
-                KeyCode::None => (),
+                KeyCode::None => Ok(()),
                 KeyCode::Consumer(code) => self.consumer_control.press(code),
                 KeyCode::Key(hid_key) => self.keyboard.press(hid_key),
             },
             HidReportType::KeyRelease { key_code } => match key_code {
-                KeyCode::None => (),
+                KeyCode::None => Ok(()),
                 KeyCode::Consumer(_) => self.consumer_control.release(),
                 KeyCode::Key(hid_key) => self.keyboard.release(hid_key),
             },
-            HidReportType::MouseMove { x, y } => self.mouse.move_to(x, y),
-            HidReportType::MouseMoveRelative { x, y } => self.mouse.move_by(x, y),
+            HidReportType::MouseMove { x, y } => match self.mouse_mode {
+                MouseMode::Relative => {
+                    let (dx, dy) = relative_move(self.mouse.get_position(), (x, y), self.screen);
+                    self.mouse.set_position(x, y);
+                    self.rel_mouse.move_by(dx, dy)
+                }
+                _ => {
+                    self.relative = false;
+                    self.mouse.move_to(x, y)
+                }
+            },
+            HidReportType::MouseMoveRelative { x, y } => {
+                let (abs_x, abs_y) = absolute_move(self.mouse.get_position(), (x, y), self.screen);
+                match self.mouse_mode {
+                    MouseMode::Absolute => self.mouse.move_to(abs_x, abs_y),
+                    _ => {
+                        self.relative = true;
+                        self.mouse.set_position(abs_x, abs_y);
+                        self.rel_mouse.move_by(x, y)
+                    }
+                }
+            }
+            HidReportType::MouseDown { button } if self.relative => {
+                self.rel_mouse.mouse_down(button)
+            }
             HidReportType::MouseDown { button } => self.mouse.mouse_down(button),
-            HidReportType::MouseUp { button } => self.mouse.mouse_up(button),
+            // In the auto mode the pointer may have changed since the press, e.g. pressed on the relative pointer then
+            // moved with an absolute move, so the button is released on the pointer holding it, or both
+            HidReportType::MouseUp { button } => {
+                let rel_held = self.rel_mouse.holds(button);
+                let abs_held = self.mouse.holds(button);
+                let mut ret = Ok(());
+                if rel_held || (self.relative && !abs_held) {
+                    ret = self.rel_mouse.mouse_up(button);
+                }
+                if abs_held || (!self.relative && !rel_held) {
+                    ret = ret.and(self.mouse.mouse_up(button));
+                }
+                ret
+            }
+            HidReportType::MouseWheel { scroll, pan } if self.relative => {
+                self.rel_mouse.mouse_wheel(scroll, pan)
+            }
             HidReportType::MouseWheel { scroll, pan } => self.mouse.mouse_wheel(scroll, pan),
         }
     }
 
-    pub fn clear(&mut self) {
-        self.mouse.clear();
-        self.keyboard.clear();
-        self.consumer_control.clear();
+    /**
+     * Releases everything, the state is cleared even if some reports fail, returns the first error.
+     */
+    pub fn clear(&mut self) -> Result<(), ReportError> {
+        let mut ret = Ok(());
+        // Only the pointers presented to the host
+        if self.mouse_mode != MouseMode::Relative {
+            ret = ret.and(self.mouse.clear());
+        }
+        if self.mouse_mode != MouseMode::Absolute {
+            ret = ret.and(self.rel_mouse.clear());
+        }
+        ret = ret.and(self.keyboard.clear());
+        ret.and(self.consumer_control.clear())
     }
 }
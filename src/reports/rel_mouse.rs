@@ -0,0 +1,48 @@
+use esparrier_core::reports::{split_move, ReportError};
+
+use super::usb_util_rel_mouse_report;
+
+pub struct RelMouseReport {
+    button: u8,
+}
+
+impl RelMouseReport {
+    pub fn new() -> Self {
+        Self { button: 0 }
+    }
+
+    pub fn move_by(&mut self, x: i16, y: i16) -> Result<(), ReportError> {
+        // The report only takes [-127, 127] each way
+        for (x, y) in split_move(x, y) {
+            self.send(x, y, 0, 0)?;
+        }
+        Ok(())
+    }
+
+    pub fn mouse_down(&mut self, button: u8) -> Result<(), ReportError> {
+        self.button |= button;
+        self.send(0, 0, 0, 0)
+    }
+
+    pub fn mouse_up(&mut self, button: u8) -> Result<(), ReportError> {
+        self.button &= !button;
+        self.send(0, 0, 0, 0)
+    }
+
+    pub fn holds(&self, button: u8) -> bool {
+        self.button & button != 0
+    }
+
+    pub fn mouse_wheel(&mut self, scroll: i8, pan: i8) -> Result<(), ReportError> {
+        self.send(0, 0, scroll, pan)
+    }
+
+    pub fn clear(&mut self) -> Result<(), ReportError> {
+        self.button = 0;
+        self.send(0, 0, 0, 0)
+    }
+
+    fn send(&self, x: i8, y: i8, scroll: i8, pan: i8) -> Result<(), ReportError> {
+        ReportError::check(unsafe { usb_util_rel_mouse_report(self.button, x, y, scroll, pan) })
+    }
+}
@@ -1,11 +1,22 @@
-use std::ffi::{CStr, CString};
+use std::{
+    ffi::{c_void, CStr, CString},
+    net::Ipv4Addr,
+};
 
 use const_env::from_env;
+use esparrier_core::{
+    layout::KeyboardLayout,
+    paste::UnicodeInput,
+    reports::MouseMode,
+    wifi::{is_valid_passphrase, passphrase_from, SavedNetwork, TtlsPhase2},
+};
 use esp_idf_sys::{
-    nvs_flash_init, nvs_get_str, nvs_get_u16, nvs_handle_t, nvs_open,
-    nvs_open_mode_t_NVS_READONLY, ESP_OK,
+    esp, esp_fill_random, nvs_close, nvs_commit, nvs_erase_key, nvs_flash_init, nvs_get_str, nvs_get_u16,
+    nvs_handle_t, nvs_open, nvs_open_mode_t_NVS_READONLY, nvs_open_mode_t_NVS_READWRITE,
+    nvs_set_str, nvs_set_u16, EspError, ESP_ERR_NVS_NOT_FOUND, ESP_OK,
 };
 use lazy_static::lazy_static;
+use log::{info, warn};
 
 // Default values are from env
 #[from_env("SCREEN_WIDTH")]
@@ -13,19 +24,70 @@ const DEFAULT_SCREEN_WIDTH: u16 = 1920;
 #[from_env("SCREEN_HEIGHT")]
 const DEFAULT_SCREEN_HEIGHT: u16 = 1080;
 #[from_env("BARRIER_SERVER")]
-const DEFAULT_BARRIER_SERVER: &str = "127.0.0.1";
+// Empty to discover the server with DNS-SD
+const DEFAULT_BARRIER_SERVER: &str = "";
 #[from_env("BARRIER_PORT")]
 const DEFAULT_BARRIER_PORT: u16 = 24800;
 #[from_env("SCREEN_NAME")]
 const DEFAULT_SCREEN_NAME: &str = "ESPARRIER";
 const DEFAULT_SSID: &str = env!("WIFI_SSID");
 const DEFAULT_PASS: &str = env!("WIFI_PASSWORD");
+#[from_env("WIFI_ENTERPRISE")]
+const DEFAULT_WIFI_ENTERPRISE: bool = false;
+const DEFAULT_EAP_IDENTITY: Option<&str> = option_env!("EAP_IDENTITY");
+const DEFAULT_EAP_USERNAME: Option<&str> = option_env!("EAP_USERNAME");
+const DEFAULT_EAP_PASSWORD: Option<&str> = option_env!("EAP_PASSWORD");
+#[from_env("EAP_TTLS_PHASE2")]
+const DEFAULT_EAP_TTLS_PHASE2: &str = "mschapv2";
+// The first network uses the keys `ssid`, `pass`, `prio` and `eap`, the others `ssid1`, `pass1`, `prio1`, `eap1` and
+// so on
+pub const WIFI_SLOTS: usize = 4;
 #[from_env("REVERSED_WHEEL")]
 const DEFAULT_REVERSED_WHEEL: bool = false;
 #[from_env("V_SCROLL_SCALE")]
 const DEFAULT_V_SCROLL_SCALE: f32 = 1.0;
 #[from_env("H_SCROLL_SCALE")]
 const DEFAULT_H_SCROLL_SCALE: f32 = 1.0;
+#[from_env("MOUSE_MODE")]
+const DEFAULT_MOUSE_MODE: &str = "absolute";
+#[from_env("REMOTE_WAKEUP")]
+const DEFAULT_REMOTE_WAKEUP: bool = true;
+#[from_env("USB_VID")]
+const DEFAULT_USB_VID: u16 = 0x0d0a;
+#[from_env("USB_PID")]
+const DEFAULT_USB_PID: u16 = 0xc0de;
+#[from_env("USB_MANUFACTURER")]
+const DEFAULT_USB_MANUFACTURER: &str = "0d0a.com";
+#[from_env("USB_PRODUCT")]
+const DEFAULT_USB_PRODUCT: &str = "Esparrier";
+// Derived from the MAC address if unset
+const DEFAULT_USB_SERIAL: Option<&str> = option_env!("USB_SERIAL");
+#[from_env("BARRIER_TLS")]
+const DEFAULT_BARRIER_TLS: bool = false;
+const DEFAULT_BARRIER_FINGERPRINT: Option<&str> = option_env!("BARRIER_FINGERPRINT");
+#[from_env("UNICODE_INPUT")]
+const DEFAULT_UNICODE_INPUT: &str = "skip";
+#[from_env("KEYBOARD_LAYOUT")]
+const DEFAULT_KEYBOARD_LAYOUT: &str = "us";
+#[from_env("PASTE_KEY_DELAY")]
+const DEFAULT_PASTE_KEY_DELAY: u16 = 5;
+#[from_env("PASTE_NEWLINE_DELAY")]
+const DEFAULT_PASTE_NEWLINE_DELAY: u16 = 0;
+#[from_env("CONFIG_PORTAL")]
+const DEFAULT_CONFIG_PORTAL: bool = false;
+const DEFAULT_STATIC_IP: Option<&str> = option_env!("STATIC_IP");
+#[from_env("NETMASK")]
+const DEFAULT_NETMASK: &str = "255.255.255.0";
+const DEFAULT_GATEWAY: Option<&str> = option_env!("GATEWAY");
+const DEFAULT_DNS: Option<&str> = option_env!("DNS");
+const DEFAULT_DNS2: Option<&str> = option_env!("DNS2");
+#[from_env("WIFI_RETRIES")]
+const DEFAULT_WIFI_RETRIES: u16 = 5;
+const DEFAULT_AP_PASSWORD: Option<&str> = option_env!("AP_PASSWORD");
+// Length of the generated setup network password
+const AP_PASSWORD_LEN: usize = 12;
+// Required to serve the configuration page on the WiFi network
+const DEFAULT_PORTAL_PASSWORD: Option<&str> = option_env!("PORTAL_PASSWORD");
 
 lazy_static! {
     static ref NVS_HANDLE: nvs_handle_t = {
@@ -49,17 +111,82 @@ lazy_static! {
     static ref BARRIER_SERVER: &'static str = get_str("barrier_server").unwrap_or(DEFAULT_BARRIER_SERVER);
     static ref BARRIER_PORT: u16 = get_u16("barrier_port").unwrap_or(DEFAULT_BARRIER_PORT);
     static ref SCREEN_NAME: &'static str = get_str("screen_name").unwrap_or(DEFAULT_SCREEN_NAME);
-    static ref SSID: &'static str = get_str("ssid").unwrap_or(DEFAULT_SSID);
-    static ref PASS: &'static str = get_str("pass").unwrap_or(DEFAULT_PASS);
+    static ref WIFI_NETWORKS: [Option<SavedNetwork<'static>>; WIFI_SLOTS] = std::array::from_fn(|slot| {
+        if slot == 0 {
+            return Some(SavedNetwork {
+                ssid: get_str("ssid").unwrap_or(DEFAULT_SSID),
+                password: get_str("pass").unwrap_or(DEFAULT_PASS),
+                priority: get_u16("prio").unwrap_or_default(),
+                enterprise: get_bool("eap").unwrap_or(DEFAULT_WIFI_ENTERPRISE),
+            });
+        }
+        let ssid = get_str(&format!("ssid{}", slot)).filter(|s| !s.is_empty())?;
+        Some(SavedNetwork {
+            ssid,
+            password: get_str(&format!("pass{}", slot)).unwrap_or_default(),
+            priority: get_u16(&format!("prio{}", slot)).unwrap_or_default(),
+            enterprise: get_bool(&format!("eap{}", slot)).unwrap_or_default(),
+        })
+    });
     static ref REVERSED_WHEEL: bool = get_bool("reversed_wheel").unwrap_or(DEFAULT_REVERSED_WHEEL);
     static ref V_SCROLL_SCALE: f32 = get_f32("v_scroll_scale").unwrap_or(DEFAULT_V_SCROLL_SCALE);
     static ref H_SCROLL_SCALE: f32 = get_f32("h_scroll_scale").unwrap_or(DEFAULT_H_SCROLL_SCALE);
+    static ref MOUSE_MODE: MouseMode = get_str("mouse_mode")
+        .unwrap_or(DEFAULT_MOUSE_MODE)
+        .parse()
+        .unwrap_or_default();
+    static ref REMOTE_WAKEUP: bool = get_bool("remote_wakeup").unwrap_or(DEFAULT_REMOTE_WAKEUP);
+    static ref USB_VID: u16 = get_u16("usb_vid").unwrap_or(DEFAULT_USB_VID);
+    static ref USB_PID: u16 = get_u16("usb_pid").unwrap_or(DEFAULT_USB_PID);
+    static ref USB_MANUFACTURER: &'static str = get_str("usb_mfr").unwrap_or(DEFAULT_USB_MANUFACTURER);
+    static ref USB_PRODUCT: &'static str = get_str("usb_product").unwrap_or(DEFAULT_USB_PRODUCT);
+    static ref USB_SERIAL: Option<&'static str> = get_str("usb_serial").or(DEFAULT_USB_SERIAL);
+    static ref BARRIER_TLS: bool = get_bool("barrier_tls").unwrap_or(DEFAULT_BARRIER_TLS);
+    static ref BARRIER_FINGERPRINT: Option<&'static str> = get_str("barrier_fp").or(DEFAULT_BARRIER_FINGERPRINT);
+    static ref UNICODE_INPUT: UnicodeInput = get_str("unicode_input")
+        .unwrap_or(DEFAULT_UNICODE_INPUT)
+        .parse()
+        .unwrap_or_default();
+    static ref KEYBOARD_LAYOUT: KeyboardLayout = get_str("keyboard_layout")
+        .unwrap_or(DEFAULT_KEYBOARD_LAYOUT)
+        .parse()
+        .unwrap_or_default();
+    static ref PASTE_KEY_DELAY: u16 = get_u16("paste_delay").unwrap_or(DEFAULT_PASTE_KEY_DELAY);
+    static ref PASTE_NEWLINE_DELAY: u16 = get_u16("paste_nl_delay").unwrap_or(DEFAULT_PASTE_NEWLINE_DELAY);
+    static ref CONFIG_PORTAL: bool = get_bool("config_portal").unwrap_or(DEFAULT_CONFIG_PORTAL);
+    static ref EAP_IDENTITY: Option<&'static str> = get_str("eap_identity").or(DEFAULT_EAP_IDENTITY);
+    static ref EAP_USERNAME: Option<&'static str> = get_str("eap_user").or(DEFAULT_EAP_USERNAME);
+    static ref EAP_PASSWORD: Option<&'static str> = get_str("eap_pass").or(DEFAULT_EAP_PASSWORD);
+    // The PEM is parsed by mbedtls which needs the terminating NUL, and the driver keeps the pointer
+    static ref EAP_CA_CERT: Option<CString> = get_str("eap_ca").map(|s| CString::new(s).unwrap_or_default());
+    static ref EAP_TTLS_PHASE2: TtlsPhase2 = get_str("eap_phase2")
+        .unwrap_or(DEFAULT_EAP_TTLS_PHASE2)
+        .parse()
+        .unwrap_or_default();
+    static ref STATIC_IP: Option<Ipv4Addr> = get_ipv4("static_ip", DEFAULT_STATIC_IP);
+    static ref NETMASK: Ipv4Addr = get_ipv4("netmask", Some(DEFAULT_NETMASK)).unwrap_or(Ipv4Addr::new(255, 255, 255, 0));
+    static ref GATEWAY: Option<Ipv4Addr> = get_ipv4("gateway", DEFAULT_GATEWAY);
+    static ref DNS: Option<Ipv4Addr> = get_ipv4("dns", DEFAULT_DNS);
+    static ref DNS2: Option<Ipv4Addr> = get_ipv4("dns2", DEFAULT_DNS2);
+    static ref WIFI_RETRIES: u16 = get_u16("wifi_retries").unwrap_or(DEFAULT_WIFI_RETRIES);
+    static ref PORTAL_PASSWORD: Option<&'static str> = get_str("portal_pass")
+        .or(DEFAULT_PORTAL_PASSWORD)
+        .filter(|p| !p.is_empty());
+    static ref AP_PASSWORD: &'static str = get_str("ap_pass")
+        .or(DEFAULT_AP_PASSWORD)
+        .filter(|p| is_valid_passphrase(p))
+        .unwrap_or_else(generate_ap_password);
 }
 
 fn get_bool(key: &str) -> Option<bool> {
     get_u16(key).map(|v| v != 0)
 }
 
+// Empty or invalid addresses are treated as unset
+fn get_ipv4(key: &str, default: Option<&'static str>) -> Option<Ipv4Addr> {
+    get_str(key).or(default)?.trim().parse().ok()
+}
+
 fn get_u16(key: &str) -> Option<u16> {
     if *NVS_HANDLE == 0 {
         return None;
@@ -79,8 +206,13 @@ fn get_str(key: &str) -> Option<&'static str> {
         return None;
     }
     let key = CString::new(key).unwrap();
-    let mut out_value: [i8; 64] = [0; 64];
-    let mut size = 64;
+    // Query the length first, some values such as the TLS fingerprint are longer than a fixed buffer
+    let mut size = 0;
+    let ret = unsafe { nvs_get_str(*NVS_HANDLE, key.as_ptr(), std::ptr::null_mut(), &mut size) };
+    if ret != ESP_OK || size == 0 {
+        return None;
+    }
+    let mut out_value: Vec<i8> = vec![0; size];
     let ret = unsafe { nvs_get_str(*NVS_HANDLE, key.as_ptr(), out_value.as_mut_ptr(), &mut size) };
     if ret == ESP_OK {
         // WARN: Deliberately leak the string, so you shouldn't repeatedly call this function for the same key
@@ -109,12 +241,71 @@ fn get_f32(key: &str) -> Option<f32> {
     }
 }
 
+/**
+ * Writes the settings into NVS, the values above are read only once, so the changes take effect after reboot.
+ */
+pub struct SettingsWriter(nvs_handle_t);
+
+impl SettingsWriter {
+    pub fn open() -> Result<Self, EspError> {
+        let name = CString::new("settings").unwrap();
+        let mut handle: nvs_handle_t = 0;
+        esp!(unsafe { nvs_open(name.as_ptr(), nvs_open_mode_t_NVS_READWRITE, &mut handle) })?;
+        Ok(Self(handle))
+    }
+
+    pub fn set_u16(&mut self, key: &str, value: u16) -> Result<(), EspError> {
+        let key = CString::new(key).unwrap();
+        esp!(unsafe { nvs_set_u16(self.0, key.as_ptr(), value) })
+    }
+
+    pub fn set_bool(&mut self, key: &str, value: bool) -> Result<(), EspError> {
+        self.set_u16(key, value as u16)
+    }
+
+    // Same scale of 100 as `get_f32`
+    pub fn set_f32(&mut self, key: &str, value: f32) -> Result<(), EspError> {
+        self.set_u16(key, (value * 100.0).round() as u16)
+    }
+
+    pub fn set_str(&mut self, key: &str, value: &str) -> Result<(), EspError> {
+        let key = CString::new(key).unwrap();
+        let value = CString::new(value).unwrap_or_default();
+        esp!(unsafe { nvs_set_str(self.0, key.as_ptr(), value.as_ptr()) })
+    }
+
+    pub fn erase(&mut self, key: &str) -> Result<(), EspError> {
+        let key = CString::new(key).unwrap();
+        match unsafe { nvs_erase_key(self.0, key.as_ptr()) } {
+            ESP_ERR_NVS_NOT_FOUND => Ok(()),
+            ret => esp!(ret),
+        }
+    }
+
+    pub fn commit(self) -> Result<(), EspError> {
+        esp!(unsafe { nvs_commit(self.0) })
+    }
+}
+
+impl Drop for SettingsWriter {
+    fn drop(&mut self) {
+        unsafe { nvs_close(self.0) }
+    }
+}
+
 pub fn get_wifi_ssid() -> &'static str {
-    *SSID
+    get_wifi_slot(0).map(|n| n.ssid).unwrap_or_default()
 }
 
-pub fn get_wifi_password() -> &'static str {
-    *PASS
+pub fn get_wifi_slot(slot: usize) -> Option<&'static SavedNetwork<'static>> {
+    WIFI_NETWORKS.get(slot)?.as_ref()
+}
+
+/**
+ * All saved networks in the order of the slots, empty slots are skipped.
+ */
+pub fn get_wifi_networks() -> Vec<SavedNetwork<'static>> {
+    WIFI_NETWORKS.iter().flatten().cloned().collect()
 }
 
 pub fn get_barrier_server() -> &'static str {
@@ -148,3 +339,135 @@ pub fn get_v_scroll_scale() -> f32 {
 pub fn get_h_scroll_scale() -> f32 {
     *H_SCROLL_SCALE
 }
+
+pub fn get_mouse_mode() -> MouseMode {
+    *MOUSE_MODE
+}
+
+pub fn get_remote_wakeup() -> bool {
+    *REMOTE_WAKEUP
+}
+
+pub fn get_usb_vid() -> u16 {
+    *USB_VID
+}
+
+pub fn get_usb_pid() -> u16 {
+    *USB_PID
+}
+
+pub fn get_usb_manufacturer() -> &'static str {
+    *USB_MANUFACTURER
+}
+
+pub fn get_usb_product() -> &'static str {
+    *USB_PRODUCT
+}
+
+pub fn get_usb_serial() -> Option<&'static str> {
+    *USB_SERIAL
+}
+
+pub fn get_barrier_tls() -> bool {
+    *BARRIER_TLS
+}
+
+pub fn get_barrier_fingerprint() -> Option<&'static str> {
+    *BARRIER_FINGERPRINT
+}
+
+pub fn get_unicode_input() -> UnicodeInput {
+    *UNICODE_INPUT
+}
+
+pub fn get_keyboard_layout() -> KeyboardLayout {
+    *KEYBOARD_LAYOUT
+}
+
+pub fn get_paste_key_delay() -> u16 {
+    *PASTE_KEY_DELAY
+}
+
+pub fn get_paste_newline_delay() -> u16 {
+    *PASTE_NEWLINE_DELAY
+}
+
+pub fn get_config_portal() -> bool {
+    *CONFIG_PORTAL
+}
+
+pub fn get_wifi_retries() -> u16 {
+    *WIFI_RETRIES
+}
+
+/**
+ * The password of the configuration page, the page is only served on the setup network without it.
+ */
+pub fn get_portal_password() -> Option<&'static str> {
+    *PORTAL_PASSWORD
+}
+
+/**
+ * The WPA2 passphrase of the setup network, a random one is generated and saved if none is set.
+ */
+pub fn get_ap_password() -> &'static str {
+    *AP_PASSWORD
+}
+
+// Saved so the password stays the same across reboots until it's changed in the configuration page
+fn generate_ap_password() -> &'static str {
+    let mut random = [0u8; AP_PASSWORD_LEN];
+    unsafe { esp_fill_random(random.as_mut_ptr() as *mut c_void, random.len()) };
+    let password = passphrase_from(&random);
+    info!("Generated setup network password");
+    if let Err(e) = SettingsWriter::open().and_then(|mut writer| {
+        writer.set_str("ap_pass", &password)?;
+        writer.commit()
+    }) {
+        warn!("Failed to save the setup network password: {}", e);
+    }
+    Box::leak(password.into_boxed_str())
+}
+
+pub fn get_eap_identity() -> Option<&'static str> {
+    *EAP_IDENTITY
+}
+
+pub fn get_eap_username() -> Option<&'static str> {
+    *EAP_USERNAME
+}
+
+pub fn get_eap_password() -> Option<&'static str> {
+    *EAP_PASSWORD
+}
+
+pub fn get_eap_ca_cert() -> Option<&'static CStr> {
+    EAP_CA_CERT.as_deref()
+}
+
+pub fn get_eap_ttls_phase2() -> TtlsPhase2 {
+    *EAP_TTLS_PHASE2
+}
+
+/**
+ * The static address of the station, DHCP is used if it is not set.
+ */
+pub fn get_static_ip() -> Option<Ipv4Addr> {
+    *STATIC_IP
+}
+
+pub fn get_netmask() -> Ipv4Addr {
+    *NETMASK
+}
+
+pub fn get_gateway() -> Option<Ipv4Addr> {
+    *GATEWAY
+}
+
+pub fn get_dns() -> Option<Ipv4Addr> {
+    *DNS
+}
+
+pub fn get_dns2() -> Option<Ipv4Addr> {
+    *DNS2
+}
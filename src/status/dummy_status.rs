@@ -12,6 +12,9 @@ pub fn set_status(status: Status) {
         Status::WifiConnected => {
             info!("Status: WifiConnected");
         }
+        Status::Provisioning => {
+            info!("Status: Provisioning");
+        }
         Status::Activated => {
             info!("Status: Activated");
         }
@@ -21,5 +24,17 @@ pub fn set_status(status: Status) {
         Status::ClipboardSize(_) => {
             info!("Status: ClipboardSize");
         }
+        Status::UnknownScreen => {
+            info!("Status: UnknownScreen");
+        }
+        Status::ScreenBusy => {
+            info!("Status: ScreenBusy");
+        }
+        Status::Incompatible => {
+            info!("Status: Incompatible");
+        }
     }
 }
+
+// Not logged, the message may contain a secret such as the setup network password
+pub fn show_message(_message: &str) {}
@@ -3,8 +3,11 @@ use std::sync::Mutex;
 use super::Status;
 use display_interface_spi::SPIInterfaceNoCS;
 use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
     pixelcolor::Rgb565,
-    prelude::{DrawTarget, RgbColor},
+    prelude::{DrawTarget, Point, RgbColor},
+    text::Text,
+    Drawable,
 };
 use esp_idf_hal::{gpio, spi};
 use lazy_static::lazy_static;
@@ -38,6 +41,9 @@ impl LCDStatusDisplay {
             Status::WifiConnected => {
                 self.display.clear(Rgb565::BLUE).unwrap();
             }
+            Status::Provisioning => {
+                self.display.clear(Rgb565::MAGENTA).unwrap();
+            }
             Status::Activated => {
                 self.display.clear(Rgb565::GREEN).unwrap();
             }
@@ -47,8 +53,26 @@ impl LCDStatusDisplay {
             Status::ClipboardSize(_) => {
                 // self.clipboard_size.store(size, Ordering::Relaxed);
             }
+            Status::UnknownScreen => {
+                // Orange
+                self.display.clear(Rgb565::new(31, 40, 0)).unwrap();
+            }
+            Status::ScreenBusy => {
+                self.display.clear(Rgb565::CYAN).unwrap();
+            }
+            Status::Incompatible => {
+                self.display.clear(Rgb565::WHITE).unwrap();
+            }
         }
     }
+
+    // Drawn on top of the status color, one line for each line of the message
+    pub fn show_message(&mut self, message: &str) {
+        let style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        Text::new(message, Point::new(4, 14), style)
+            .draw(&mut self.display)
+            .ok();
+    }
 }
 
 lazy_static! {
@@ -66,3 +90,9 @@ pub fn set_status(status: Status) {
         display.set_status(status)
     }
 }
+
+pub fn show_message(message: &str) {
+    if let Some(display) = DISPLAY.lock().unwrap().as_mut() {
+        display.show_message(message)
+    }
+}
@@ -4,9 +4,14 @@ pub enum Status {
     None,
     Start,
     WifiConnected,
+    Provisioning,
     Activated,
     Deactivated,
     ClipboardSize(usize),
+    // Rejected by the server, fix the server configuration
+    UnknownScreen,
+    ScreenBusy,
+    Incompatible,
 }
 
 pub trait StatusDisplay {
@@ -18,16 +23,16 @@ pub trait StatusDisplay {
 mod neopixel_status;
 
 #[cfg(feature = "m5atoms3lite")]
-pub use neopixel_status::set_status;
+pub use neopixel_status::{set_status, show_message};
 
 #[cfg(feature = "m5atoms3")]
 pub mod lcd_status;
 
 #[cfg(feature = "m5atoms3")]
-pub use lcd_status::set_status;
+pub use lcd_status::{set_status, show_message};
 
 // For boards without status display
 #[cfg(not(any(feature = "m5atoms3", feature = "m5atoms3lite")))]
 pub mod dummy_status;
 #[cfg(not(any(feature = "m5atoms3", feature = "m5atoms3lite")))]
-pub use dummy_status::set_status;
+pub use dummy_status::{set_status, show_message};
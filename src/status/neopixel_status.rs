@@ -47,9 +47,13 @@ impl NeoPixelStatusTask {
             Status::None => self.set_color(RGB::new(0, 0, 0)),
             Status::Start => self.set_color(RGB::new(128, 0, 0)),
             Status::WifiConnected => self.set_color(RGB::new(0, 0, 128)),
+            Status::Provisioning => self.set_color(RGB::new(64, 0, 64)),
             Status::Activated => self.set_color(RGB::new(0, 64, 0)),
             Status::Deactivated => self.set_color(RGB::new(40, 24, 0)),
             Status::ClipboardSize(_) => {}
+            Status::UnknownScreen => self.set_color(RGB::new(96, 32, 0)),
+            Status::ScreenBusy => self.set_color(RGB::new(0, 48, 48)),
+            Status::Incompatible => self.set_color(RGB::new(48, 48, 48)),
         }
     }
 
@@ -62,7 +66,12 @@ impl NeoPixelStatusTask {
                 Ok(status) => {
                     if status != current_status {
                         match status {
-                            Status::Start | Status::WifiConnected => {
+                            Status::Start
+                            | Status::WifiConnected
+                            | Status::Provisioning
+                            | Status::UnknownScreen
+                            | Status::ScreenBusy
+                            | Status::Incompatible => {
                                 debug!("Start flashing LED");
                                 self.set_status(status);
                                 flash_start = Some(Instant::now());
@@ -129,3 +138,6 @@ lazy_static :: lazy_static! {
 pub fn set_status(status: Status) {
     STATUS_DISPLAY.set_status(status);
 }
+
+// The LED can't show text
+pub fn show_message(_message: &str) {}
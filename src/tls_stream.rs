@@ -0,0 +1,235 @@
+use std::{
+    ffi::{c_int, c_uchar, c_void, CString},
+    io::{self, Read, Write},
+    net::TcpStream,
+};
+
+use esp_idf_sys::{
+    esp_fill_random, mbedtls_ssl_close_notify, mbedtls_ssl_conf_authmode, mbedtls_ssl_conf_rng,
+    mbedtls_ssl_config, mbedtls_ssl_config_defaults, mbedtls_ssl_config_free,
+    mbedtls_ssl_config_init, mbedtls_ssl_context, mbedtls_ssl_free, mbedtls_ssl_get_peer_cert,
+    mbedtls_ssl_handshake, mbedtls_ssl_init, mbedtls_ssl_read, mbedtls_ssl_set_bio,
+    mbedtls_ssl_set_hostname, mbedtls_ssl_setup, mbedtls_ssl_write,
+    MBEDTLS_ERR_SSL_PEER_CLOSE_NOTIFY, MBEDTLS_ERR_SSL_WANT_READ, MBEDTLS_ERR_SSL_WANT_WRITE,
+    MBEDTLS_SSL_IS_CLIENT, MBEDTLS_SSL_PRESET_DEFAULT, MBEDTLS_SSL_TRANSPORT_STREAM,
+    MBEDTLS_SSL_VERIFY_NONE,
+};
+use esparrier_core::barrier::{ConnectionError, Fingerprint, ReadTimeout};
+use log::{debug, info, warn};
+
+// Defined in `mbedtls/net_sockets.h`, which is not part of the generated bindings
+const MBEDTLS_ERR_NET_SEND_FAILED: c_int = -0x004E;
+const MBEDTLS_ERR_NET_RECV_FAILED: c_int = -0x004C;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TlsConfig {
+    /**
+     * Barrier servers use self-signed certificates, so the only way to authenticate the server is to pin its
+     * fingerprint, the connection is still encrypted but not authenticated if this is `None`.
+     */
+    pub fingerprint: Option<Fingerprint>,
+}
+
+// The socket and the last I/O error seen by the BIO callbacks, boxed so mbedtls can hold a stable pointer to it
+struct Bio {
+    socket: TcpStream,
+    last_error: Option<io::Error>,
+}
+
+/**
+ * TLS client stream on top of a `TcpStream`, backed by the mbedtls shipped with ESP-IDF.
+ */
+pub struct TlsStream {
+    ssl: Box<mbedtls_ssl_context>,
+    conf: Box<mbedtls_ssl_config>,
+    bio: Box<Bio>,
+}
+
+// The mbedtls contexts are only accessed through `&mut self`
+unsafe impl Send for TlsStream {}
+
+unsafe extern "C" fn tls_rng(_: *mut c_void, output: *mut c_uchar, len: usize) -> c_int {
+    esp_fill_random(output as *mut c_void, len);
+    0
+}
+
+unsafe extern "C" fn tls_send(ctx: *mut c_void, buf: *const c_uchar, len: usize) -> c_int {
+    let bio = &mut *(ctx as *mut Bio);
+    match bio.socket.write(std::slice::from_raw_parts(buf, len)) {
+        Ok(n) => n as c_int,
+        Err(e) => {
+            bio.last_error = Some(e);
+            MBEDTLS_ERR_NET_SEND_FAILED
+        }
+    }
+}
+
+unsafe extern "C" fn tls_recv(ctx: *mut c_void, buf: *mut c_uchar, len: usize) -> c_int {
+    let bio = &mut *(ctx as *mut Bio);
+    match bio.socket.read(std::slice::from_raw_parts_mut(buf, len)) {
+        Ok(n) => n as c_int,
+        // A read timeout, mbedtls keeps the partial record so the read can be retried later
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            bio.last_error = Some(e);
+            MBEDTLS_ERR_SSL_WANT_READ
+        }
+        Err(e) => {
+            bio.last_error = Some(e);
+            MBEDTLS_ERR_NET_RECV_FAILED
+        }
+    }
+}
+
+impl TlsStream {
+    pub fn connect(
+        socket: TcpStream,
+        server_name: &str,
+        config: &TlsConfig,
+    ) -> Result<Self, ConnectionError> {
+        let mut stream = Self {
+            ssl: Box::new(unsafe { std::mem::zeroed() }),
+            conf: Box::new(unsafe { std::mem::zeroed() }),
+            bio: Box::new(Bio {
+                socket,
+                last_error: None,
+            }),
+        };
+        unsafe {
+            mbedtls_ssl_init(&mut *stream.ssl);
+            mbedtls_ssl_config_init(&mut *stream.conf);
+            check(mbedtls_ssl_config_defaults(
+                &mut *stream.conf,
+                MBEDTLS_SSL_IS_CLIENT as c_int,
+                MBEDTLS_SSL_TRANSPORT_STREAM as c_int,
+                MBEDTLS_SSL_PRESET_DEFAULT as c_int,
+            ))?;
+            // Barrier uses self-signed certificates, the certificate is checked against the fingerprint instead
+            mbedtls_ssl_conf_authmode(&mut *stream.conf, MBEDTLS_SSL_VERIFY_NONE as c_int);
+            mbedtls_ssl_conf_rng(&mut *stream.conf, Some(tls_rng), std::ptr::null_mut());
+            check(mbedtls_ssl_setup(&mut *stream.ssl, &*stream.conf))?;
+            let server_name = CString::new(server_name)
+                .map_err(|_| ConnectionError::TlsSetupError("invalid server name"))?;
+            check(mbedtls_ssl_set_hostname(
+                &mut *stream.ssl,
+                server_name.as_ptr(),
+            ))?;
+            mbedtls_ssl_set_bio(
+                &mut *stream.ssl,
+                &mut *stream.bio as *mut Bio as *mut c_void,
+                Some(tls_send),
+                Some(tls_recv),
+                None,
+            );
+        }
+
+        info!("Starting TLS handshake...");
+        loop {
+            match unsafe { mbedtls_ssl_handshake(&mut *stream.ssl) } {
+                0 => break,
+                MBEDTLS_ERR_SSL_WANT_READ if stream.bio.last_error.is_some() => {
+                    return Err(stream.take_error(MBEDTLS_ERR_SSL_WANT_READ).into())
+                }
+                MBEDTLS_ERR_SSL_WANT_READ | MBEDTLS_ERR_SSL_WANT_WRITE => continue,
+                e => return Err(stream.take_error(e).into()),
+            }
+        }
+
+        let fingerprint = stream.peer_fingerprint()?;
+        info!("Server certificate fingerprint: {}", fingerprint);
+        match config.fingerprint {
+            Some(expected) if expected != fingerprint => {
+                warn!(
+                    "Server certificate fingerprint mismatch, expected: {}",
+                    expected
+                );
+                return Err(ConnectionError::FingerprintMismatch);
+            }
+            Some(_) => debug!("Server certificate fingerprint matched"),
+            None => warn!(
+                "Server certificate fingerprint is not pinned, the server is not authenticated"
+            ),
+        }
+        Ok(stream)
+    }
+
+    fn peer_fingerprint(&self) -> Result<Fingerprint, ConnectionError> {
+        let cert = unsafe { mbedtls_ssl_get_peer_cert(&*self.ssl) };
+        if cert.is_null() {
+            return Err(ConnectionError::TlsSetupError("no server certificate"));
+        }
+        let der = unsafe { std::slice::from_raw_parts((*cert).raw.p, (*cert).raw.len) };
+        Ok(Fingerprint::of(der))
+    }
+
+    // Prefer the socket error that caused the failure, so timeouts are still reported as timeouts
+    fn take_error(&mut self, code: c_int) -> io::Error {
+        self.bio.last_error.take().unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::Other, format!("TLS error -0x{:04X}", -code))
+        })
+    }
+}
+
+fn check(code: c_int) -> Result<(), ConnectionError> {
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(ConnectionError::TlsError(code))
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match unsafe { mbedtls_ssl_read(&mut *self.ssl, buf.as_mut_ptr(), buf.len()) } {
+                n if n >= 0 => return Ok(n as usize),
+                // The timeout of the socket
+                MBEDTLS_ERR_SSL_WANT_READ if self.bio.last_error.is_some() => {
+                    return Err(self.take_error(MBEDTLS_ERR_SSL_WANT_READ))
+                }
+                MBEDTLS_ERR_SSL_WANT_READ | MBEDTLS_ERR_SSL_WANT_WRITE => continue,
+                MBEDTLS_ERR_SSL_PEER_CLOSE_NOTIFY => return Ok(0),
+                e => return Err(self.take_error(e)),
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match unsafe { mbedtls_ssl_write(&mut *self.ssl, buf.as_ptr(), buf.len()) } {
+                n if n >= 0 => return Ok(n as usize),
+                MBEDTLS_ERR_SSL_WANT_READ | MBEDTLS_ERR_SSL_WANT_WRITE => continue,
+                e => return Err(self.take_error(e)),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.bio.socket.flush()
+    }
+}
+
+impl ReadTimeout for TlsStream {
+    fn set_read_timeout(
+        &mut self,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<(), std::io::Error> {
+        self.bio.socket.set_read_timeout(timeout)
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        unsafe {
+            mbedtls_ssl_close_notify(&mut *self.ssl);
+            mbedtls_ssl_free(&mut *self.ssl);
+            mbedtls_ssl_config_free(&mut *self.conf);
+        }
+    }
+}
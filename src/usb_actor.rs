@@ -1,17 +1,23 @@
+use std::time::{Duration, Instant};
+
 use crate::{
     settings::*,
     status::{set_status, Status},
     CLIPBOARD,
 };
+use esparrier_core::reports::{LockState, ReportError, UsbState};
 use log::{debug, info, warn};
 
 use crate::{
-    barrier::Actuator,
+    barrier::{Actuator, DeviceOptions, KeyOptions},
     keycodes::{synergy_mouse_button, synergy_to_hid, KeyCode},
-    reports::{HidReport, HidReportType},
+    reports::{mac_serial, usb_state, HidReport, HidReportType, UsbIdentity},
     INIT_USB,
 };
 
+// No more wakeup for a while after one, the host may not resume and the inputs would keep waking it up
+const WAKEUP_BACKOFF: Duration = Duration::from_secs(10);
+
 pub struct UsbHidActuator {
     pub width: u16,
     pub height: u16,
@@ -23,6 +29,12 @@ pub struct UsbHidActuator {
 
     hid_report: HidReport,
     server_buttons: [u16; 512],
+    // Modifier remapping and half-duplex locks set by the server
+    key_options: KeyOptions,
+    // The host locks, from the keyboard LEDs, matched to the server on enter
+    locks: LockState,
+    // When the remote wakeup was requested, until the host is seen awake
+    wakeup_requested: Option<Instant>,
 }
 
 impl UsbHidActuator {
@@ -35,18 +47,87 @@ impl UsbHidActuator {
             flip_mouse_wheel: get_reversed_wheel(),
             v_scroll_scale: get_v_scroll_scale(),
             h_scroll_scale: get_h_scroll_scale(),
-            hid_report: HidReport::new(),
+            hid_report: HidReport::new(
+                get_mouse_mode(),
+                get_remote_wakeup(),
+                UsbIdentity {
+                    vid: get_usb_vid(),
+                    pid: get_usb_pid(),
+                    manufacturer: get_usb_manufacturer().to_owned(),
+                    product: get_usb_product().to_owned(),
+                    serial: get_usb_serial().map_or_else(mac_serial, str::to_owned),
+                },
+                width,
+                height,
+            ),
             server_buttons: [0; 512],
+            key_options: KeyOptions::default(),
+            locks: LockState::default(),
+            wakeup_requested: None,
+        }
+    }
+
+    // The host not taking reports must not block the actuator, the reports are dropped
+    fn send(&mut self, report: HidReportType) {
+        match usb_state() {
+            // The input wakes up the sleeping host, the reports are queued until it resumes
+            UsbState::Suspended => self.wake_up(),
+            _ => self.wakeup_requested = None,
+        }
+        if let Err(e) = self.hid_report.send(report) {
+            report_error(e);
+        }
+    }
+
+    // Doesn't wait for the host to resume, the reports queued meanwhile are sent from the resume callback
+    fn wake_up(&mut self) {
+        if matches!(self.wakeup_requested, Some(t) if t.elapsed() < WAKEUP_BACKOFF) {
+            return;
+        }
+        self.wakeup_requested = Some(Instant::now());
+        if self.hid_report.remote_wakeup() {
+            info!("Waking up the host");
+        } else {
+            debug!("Remote wakeup not available");
+        }
+    }
+
+    fn clear_reports(&mut self) {
+        if let Err(e) = self.hid_report.clear() {
+            report_error(e);
+        }
+    }
+
+    fn press(&mut self, key_code: KeyCode) {
+        if let KeyCode::Key(key) = key_code {
+            self.locks.pressed(key);
+        }
+        self.send(HidReportType::KeyPress { key_code });
+    }
+
+    // Takes the lock state reported by the host, if it did since the last time
+    fn sync_locks(&mut self) {
+        if let Some(leds) = self.hid_report.poll_keyboard_leds() {
+            debug!("Keyboard LEDs {leds:#04x}");
+            self.locks.set_leds(leds);
         }
     }
 
     fn clear(&mut self) {
         info!("Clear");
-        self.hid_report.clear();
+        self.clear_reports();
         self.server_buttons.fill(0);
     }
 }
 
+fn report_error(e: ReportError) {
+    match e {
+        // Expected while the host sleeps or the cable only carries power
+        ReportError::NotMounted | ReportError::Suspended => debug!("Report dropped, {e}"),
+        _ => warn!("Report dropped, {e}"),
+    }
+}
+
 impl Actuator for UsbHidActuator {
     fn connected(&mut self) {
         info!("Connected");
@@ -58,6 +139,12 @@ impl Actuator for UsbHidActuator {
 
     fn disconnected(&mut self) {
         info!("Disconnected");
+        // Blue, back to waiting for the server
+        set_status(Status::WifiConnected);
+        // Don't leave keys stuck down until the next connection
+        self.clear();
+        // The next server sends its own options
+        self.key_options = KeyOptions::default();
     }
 
     fn get_screen_size(&self) -> (u16, u16) {
@@ -70,25 +157,24 @@ impl Actuator for UsbHidActuator {
 
     fn set_cursor_position(&mut self, x: u16, y: u16) {
         debug!("Set cursor position to {x} {y}");
-        self.hid_report.send(HidReportType::MouseMove { x, y });
+        self.send(HidReportType::MouseMove { x, y });
     }
 
     fn move_cursor(&mut self, x: i16, y: i16) {
         debug!("Move cursor by {x} {y}");
-        self.hid_report
-            .send(HidReportType::MouseMoveRelative { x, y });
+        self.send(HidReportType::MouseMoveRelative { x, y });
     }
 
     fn mouse_down(&mut self, button: i8) {
         debug!("Mouse down {button}");
-        self.hid_report.send(HidReportType::MouseDown {
+        self.send(HidReportType::MouseDown {
             button: synergy_mouse_button(button),
         });
     }
 
     fn mouse_up(&mut self, button: i8) {
         debug!("Mouse up {button}");
-        self.hid_report.send(HidReportType::MouseUp {
+        self.send(HidReportType::MouseUp {
             button: synergy_mouse_button(button),
         });
     }
@@ -102,12 +188,12 @@ impl Actuator for UsbHidActuator {
         let y = (y as f32 * self.v_scroll_scale / 120.0) as i16;
         debug!("Mouse wheel {x} {y}");
         if self.flip_mouse_wheel {
-            self.hid_report.send(HidReportType::MouseWheel {
+            self.send(HidReportType::MouseWheel {
                 scroll: -y as i8,
                 pan: -x as i8,
             });
         } else {
-            self.hid_report.send(HidReportType::MouseWheel {
+            self.send(HidReportType::MouseWheel {
                 scroll: y as i8,
                 pan: x as i8,
             });
@@ -116,6 +202,10 @@ impl Actuator for UsbHidActuator {
 
     fn key_down(&mut self, key: u16, mask: u16, button: u16) {
         debug!("Key down {key} {mask} {button}");
+        let Some(key) = self.key_options.map_key(key) else {
+            debug!("Modifier disabled");
+            return;
+        };
         self.server_buttons[button as usize] = key;
         let hid = synergy_to_hid(key);
         if INIT_USB {
@@ -127,8 +217,18 @@ impl Actuator for UsbHidActuator {
             warn!("Keycode not found");
             return;
         }
-        self.hid_report
-            .send(HidReportType::KeyPress { key_code: hid });
+        if self.key_options.is_half_duplex(key) {
+            self.sync_locks();
+            if matches!(hid, KeyCode::Key(k) if self.locks.is_locked(k)) {
+                debug!("Already locked");
+                return;
+            }
+            // The lock turns on, the key up comes when it turns off
+            self.press(hid);
+            self.send(HidReportType::KeyRelease { key_code: hid });
+            return;
+        }
+        self.press(hid);
     }
 
     fn key_repeat(&mut self, key: u16, mask: u16, button: u16, count: u16) {
@@ -138,16 +238,20 @@ impl Actuator for UsbHidActuator {
 
     fn key_up(&mut self, _key: u16, mask: u16, button: u16) {
         debug!("Key up {_key} {mask} {button}");
+        if self.key_options.map_key(_key).is_none() {
+            debug!("Modifier disabled");
+            return;
+        }
         let key = self.server_buttons[button as usize];
         if self.server_buttons[button as usize] != 0 {
             debug!("Key {key} up");
             self.server_buttons[button as usize] = 0;
         } else if key == 0 {
             info!("Key 0 up, clear all key down");
-            self.hid_report.clear();
+            self.clear_reports();
         } else {
             warn!("Key {key} up with no key down");
-            self.hid_report.clear();
+            self.clear_reports();
         }
         let hid = synergy_to_hid(key);
         if INIT_USB {
@@ -159,8 +263,16 @@ impl Actuator for UsbHidActuator {
             warn!("Keycode not found");
             return;
         }
-        self.hid_report
-            .send(HidReportType::KeyRelease { key_code: hid });
+        if self.key_options.is_half_duplex(key) {
+            self.sync_locks();
+            if matches!(hid, KeyCode::Key(k) if !self.locks.is_locked(k)) {
+                debug!("Already unlocked");
+                return;
+            }
+            // The lock turns off with another press
+            self.press(hid);
+        }
+        self.send(HidReportType::KeyRelease { key_code: hid });
     }
 
     fn set_clipboard(&mut self, mut data: Vec<u8>) {
@@ -168,19 +280,38 @@ impl Actuator for UsbHidActuator {
         std::mem::swap(CLIPBOARD.lock().unwrap().as_mut(), &mut data);
     }
 
-    fn set_options(&mut self, heartbeat: u32) {
-        info!("Set heartbeat {heartbeat}")
+    // The clipboard of the board is published by the `ThreadedActuator` wrapping this one
+    fn take_clipboard(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn set_options(&mut self, options: DeviceOptions) {
+        info!("Set options {:?}", options);
+        self.key_options.apply(&options);
     }
 
     fn reset_options(&mut self) {
-        info!("Reset options")
+        info!("Reset options");
+        self.key_options = KeyOptions::default();
     }
 
-    fn enter(&mut self) {
-        info!("Enter");
+    fn enter(&mut self, x: u16, y: u16, mask: u16) {
+        info!("Enter at {x} {y}, mask {mask:#06x}");
+        if INIT_USB && usb_state() != UsbState::Mounted {
+            warn!("USB is {:?}, the inputs are dropped", usb_state());
+        }
         // Lighter green
         set_status(Status::Activated);
         self.clear();
+        self.send(HidReportType::MouseMove { x, y });
+        self.sync_locks();
+        for key in self.locks.keys_to_match(mask) {
+            debug!("Toggle lock {key:#04x}");
+            self.press(KeyCode::Key(key));
+            self.send(HidReportType::KeyRelease {
+                key_code: KeyCode::Key(key),
+            });
+        }
     }
 
     fn leave(&mut self) {
@@ -191,13 +322,11 @@ impl Actuator for UsbHidActuator {
     }
 
     fn hid_key_down(&mut self, key: u8) {
-        self.hid_report.send(HidReportType::KeyPress {
-            key_code: KeyCode::Key(key),
-        });
+        self.press(KeyCode::Key(key));
     }
 
     fn hid_key_up(&mut self, key: u8) {
-        self.hid_report.send(HidReportType::KeyRelease {
+        self.send(HidReportType::KeyRelease {
             key_code: KeyCode::Key(key),
         });
     }
@@ -1,20 +1,232 @@
+use std::net::Ipv4Addr;
+
 use anyhow::Result;
 use esp_idf_hal::peripheral;
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
-    wifi::{BlockingWifi, EspWifi},
+    ipv4::{
+        self, ClientConfiguration as IpClientConfiguration, ClientSettings, DHCPClientSettings,
+        Mask, Subnet,
+    },
+    netif::{EspNetif, NetifConfiguration, NetifStack},
+    wifi::{BlockingWifi, EspWifi, WifiDriver},
+};
+use esp_idf_sys::{esp, esp_pm_configure};
+use esparrier_core::{
+    barrier::Watchdog,
+    wifi::{hostname_from, netmask_prefix, rank_networks, ScannedAp, TtlsPhase2},
+};
+use log::{info, warn};
+
+use embedded_svc::wifi::{
+    AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration,
 };
-use esp_idf_sys::esp_pm_configure;
-use log::info;
 
-use embedded_svc::wifi::{ClientConfiguration, Configuration};
+use crate::settings::{
+    get_ap_password, get_dns, get_dns2, get_eap_ca_cert, get_eap_identity, get_eap_password,
+    get_eap_ttls_phase2, get_eap_username, get_gateway, get_netmask, get_screen_name,
+    get_static_ip, get_wifi_networks, get_wifi_retries,
+};
+
+pub enum WifiMode {
+    Station,
+    // Provisioning, serving its own network at the given address
+    AccessPoint(Ipv4Addr),
+}
+
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/**
+ * The station interface, with the static address from the settings, or DHCP with the screen name as the hostname.
+ */
+fn sta_netif() -> Result<EspNetif> {
+    let ip_configuration = match get_static_ip() {
+        Some(ip) => {
+            let mask = netmask_prefix(get_netmask()).unwrap_or_else(|| {
+                warn!("Invalid netmask {}, using 255.255.255.0", get_netmask());
+                24
+            });
+            info!("Using static IP {}/{}", ip, mask);
+            IpClientConfiguration::Fixed(ClientSettings {
+                ip,
+                subnet: Subnet {
+                    gateway: get_gateway().unwrap_or(Ipv4Addr::UNSPECIFIED),
+                    mask: Mask(mask),
+                },
+                dns: get_dns(),
+                secondary_dns: get_dns2(),
+            })
+        }
+        None => {
+            let hostname = hostname_from(get_screen_name(), 30);
+            info!("Using DHCP with hostname {}", hostname);
+            IpClientConfiguration::DHCP(DHCPClientSettings {
+                hostname: Some(hostname.as_str().into()),
+            })
+        }
+    };
+    Ok(EspNetif::new_with_conf(&NetifConfiguration {
+        ip_configuration: ipv4::Configuration::Client(ip_configuration),
+        ..NetifConfiguration::wifi_default_client()
+    })?)
+}
 
-use crate::settings::{get_wifi_password, get_wifi_ssid};
+/**
+ * Turns 802.1X on with the enterprise credentials from the settings, or off for personal networks. PEAP or EAP-TTLS
+ * is chosen by the server.
+ */
+fn set_enterprise(enable: bool) -> Result<()> {
+    if !enable {
+        esp!(unsafe { esp_idf_sys::esp_wifi_sta_wpa2_ent_disable() })?;
+        return Ok(());
+    }
+    let username = get_eap_username().unwrap_or_default();
+    // The outer identity is sent in clear text, some servers want anonymous@realm here
+    let identity = get_eap_identity().unwrap_or(username);
+    let password = get_eap_password().unwrap_or_default();
+    let phase2 = match get_eap_ttls_phase2() {
+        TtlsPhase2::MsChapV2 => esp_idf_sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_MSCHAPV2,
+        TtlsPhase2::MsChap => esp_idf_sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_MSCHAP,
+        TtlsPhase2::Pap => esp_idf_sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_PAP,
+        TtlsPhase2::Chap => esp_idf_sys::esp_eap_ttls_phase2_types_ESP_EAP_TTLS_PHASE2_CHAP,
+    };
+    unsafe {
+        esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_identity(
+            identity.as_ptr(),
+            identity.len() as i32
+        ))?;
+        esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_username(
+            username.as_ptr(),
+            username.len() as i32
+        ))?;
+        esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_password(
+            password.as_ptr(),
+            password.len() as i32
+        ))?;
+        esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_ttls_phase2_method(
+            phase2
+        ))?;
+        match get_eap_ca_cert() {
+            Some(cert) => {
+                let cert = cert.to_bytes_with_nul();
+                esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_ca_cert(
+                    cert.as_ptr(),
+                    cert.len() as i32
+                ))?;
+            }
+            None => {
+                warn!("No CA certificate for the enterprise network, the server is not verified");
+                esp_idf_sys::esp_wifi_sta_wpa2_ent_clear_ca_cert();
+            }
+        }
+        esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_enable())?;
+    }
+    Ok(())
+}
 
-pub fn wifi(
+/**
+ * Scans and tries every saved network once, in the order of `rank_networks`.
+ */
+fn connect_known<W: Watchdog>(
+    wifi: &mut BlockingWifi<&mut EspWifi<'static>>,
+    watchdog: &mut W,
+) -> Result<()> {
+    let networks = get_wifi_networks();
+    let aps = wifi.scan().unwrap_or_else(|e| {
+        warn!("Failed to scan wifi: {}", e);
+        vec![]
+    });
+    let scan: Vec<ScannedAp> = aps
+        .iter()
+        .map(|ap| ScannedAp {
+            ssid: ap.ssid.as_str(),
+            bssid: ap.bssid,
+            channel: ap.channel,
+            rssi: ap.signal_strength,
+        })
+        .collect();
+    for candidate in rank_networks(&networks, &scan) {
+        let network = &networks[candidate.network];
+        // Each attempt may take several seconds
+        watchdog.feed();
+        info!(
+            "Connecting wifi {} on channel {:?}...",
+            network.ssid, candidate.channel
+        );
+        let (auth_method, password) = if network.enterprise {
+            // There is no WPA3-Enterprise in the WiFi driver, only networks accepting WPA2-Enterprise work
+            (AuthMethod::WPA2Enterprise, "")
+        } else {
+            (AuthMethod::default(), truncate(network.password, 64))
+        };
+        wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+            ssid: truncate(network.ssid, 32).into(),
+            bssid: candidate.bssid,
+            auth_method,
+            password: password.into(),
+            channel: candidate.channel,
+            ..Default::default()
+        }))?;
+        set_enterprise(network.enterprise)?;
+        let ret = wifi.connect().and_then(|_| {
+            info!("Waiting for IP address...");
+            wifi.wait_netif_up()
+        });
+        match ret {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                warn!("Failed to connect wifi {}: {}", network.ssid, e);
+                wifi.disconnect().ok();
+            }
+        }
+    }
+    Err(anyhow::anyhow!("No saved wifi network available"))
+}
+
+/**
+ * Reconnects to the best saved network if the current one is gone, e.g. the board was carried to another place.
+ */
+pub fn ensure_wifi<W: Watchdog>(
+    esp_wifi: &mut EspWifi<'static>,
+    sysloop: EspSystemEventLoop,
+    watchdog: &mut W,
+) -> Result<()> {
+    let mut wifi = BlockingWifi::wrap(esp_wifi, sysloop)?;
+    if wifi.is_connected()? && wifi.is_up()? {
+        return Ok(());
+    }
+    warn!("Wifi disconnected, looking for saved networks...");
+    wifi.disconnect().ok();
+    connect_known(&mut wifi, watchdog)?;
+    info!(
+        "Wifi IP info: {:?}",
+        wifi.wifi().sta_netif().get_ip_info()?
+    );
+    Ok(())
+}
+
+/**
+ * The name of the access point served when no saved network is available.
+ */
+pub fn setup_ssid() -> String {
+    format!("{}-SETUP", truncate(get_screen_name(), 26))
+}
+
+/**
+ * Connects to the best saved network, falls back to an access point named after the screen name if it fails
+ * `wifi_retries` times in a row, so the settings can be fixed from the configuration page.
+ */
+pub fn wifi<W: Watchdog>(
     modem: impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
     sysloop: EspSystemEventLoop,
-) -> Result<Box<EspWifi<'static>>> {
+    watchdog: &mut W,
+) -> Result<(Box<EspWifi<'static>>, WifiMode)> {
     unsafe {
         let cfg = esp_idf_sys::esp_pm_config_esp32s3_t {
             max_freq_mhz: 240,
@@ -24,7 +236,11 @@ pub fn wifi(
         let ret = esp_pm_configure(&cfg as *const _ as *const std::ffi::c_void);
         info!("Set power management configuration: {}", ret);
     }
-    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+    let mut esp_wifi = EspWifi::wrap_all(
+        WifiDriver::new(modem, sysloop.clone(), None)?,
+        sta_netif()?,
+        EspNetif::new(NetifStack::Ap)?,
+    )?;
 
     let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sysloop)?;
 
@@ -34,29 +250,47 @@ pub fn wifi(
 
     wifi.start()?;
 
-    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
-        ssid: get_wifi_ssid().into(),
-        password: get_wifi_password().into(),
-        channel: None,
-        ..Default::default()
-    }))?;
-
-    info!("Connecting wifi...");
-
-    wifi.connect()?;
-
-    info!("Waiting for DHCP lease...");
+    let mut connected = false;
+    for attempt in 1..=get_wifi_retries().max(1) {
+        info!("Connecting wifi, attempt {}...", attempt);
+        match connect_known(&mut wifi, watchdog) {
+            Ok(_) => {
+                connected = true;
+                break;
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
 
-    wifi.wait_netif_up()?;
+    if !connected {
+        let ssid = setup_ssid();
+        // Never open, anyone joining the setup network can rewrite the settings
+        let password = get_ap_password();
+        // The password is only shown on the screen, logs may be captured
+        info!("Starting access point {}...", ssid);
+        wifi.stop()?;
+        wifi.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+            ssid: ssid.as_str().into(),
+            password: password.into(),
+            auth_method: AuthMethod::WPA2Personal,
+            channel: 1,
+            ..Default::default()
+        }))?;
+        wifi.start()?;
+        wifi.wait_netif_up()?;
+        let ip_info = wifi.wifi().ap_netif().get_ip_info()?;
+        info!("Access point info: {:?}", ip_info);
+        return Ok((Box::new(esp_wifi), WifiMode::AccessPoint(ip_info.ip)));
+    }
 
     let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
 
-    info!("Wifi DHCP info: {:?}", ip_info);
+    info!("Wifi IP info: {:?}", ip_info);
 
     unsafe {
         let ret = esp_idf_sys::esp_wifi_set_ps(esp_idf_sys::wifi_ps_type_t_WIFI_PS_NONE);
         info!("Set WiFi power management configuration: {}", ret);
     }
 
-    Ok(Box::new(esp_wifi))
+    Ok((Box::new(esp_wifi), WifiMode::Station))
 }
@@ -4,7 +4,12 @@
  * SPDX-License-Identifier: Unlicense OR CC0-1.0
  */
 
+#include <stdio.h>
 #include <stdlib.h>
+#include <string.h>
+#include "freertos/FreeRTOS.h"
+#include "freertos/queue.h"
+#include "freertos/task.h"
 #include <device/usbd_pvt.h>
 #include "esp_log.h"
 #include "tinyusb.h"
@@ -67,32 +72,85 @@ enum {
     RID_KEYBOARD = 1,
     RID_MOUSE,
     RID_CONSUMER_CONTROL,
+    RID_REL_MOUSE,
+};
+
+// Same as `MouseMode` in Rust
+enum {
+    MOUSE_MODE_ABSOLUTE = 0,
+    MOUSE_MODE_RELATIVE,
+    MOUSE_MODE_AUTO,
 };
 
 /**
- * @brief HID report descriptor
+ * @brief HID report descriptors
  *
- * In this example we implement Keyboard + Mouse HID device,
- * so we must define both report descriptors
+ * Keyboard, consumer control and the pointers of the mouse mode, the absolute one, the relative one, or both
  */
-const uint8_t hid_report_descriptor[] = {
+static const uint8_t hid_report_descriptor_abs[] = {
     TUD_HID_REPORT_DESC_KEYBOARD(HID_REPORT_ID(RID_KEYBOARD)),
     TUD_HID_REPORT_DESC_MOUSE_ABS(HID_REPORT_ID(RID_MOUSE)),
     TUD_HID_REPORT_DESC_CONSUMER(HID_REPORT_ID(RID_CONSUMER_CONTROL))
     };
 
+static const uint8_t hid_report_descriptor_rel[] = {
+    TUD_HID_REPORT_DESC_KEYBOARD(HID_REPORT_ID(RID_KEYBOARD)),
+    TUD_HID_REPORT_DESC_CONSUMER(HID_REPORT_ID(RID_CONSUMER_CONTROL)),
+    TUD_HID_REPORT_DESC_MOUSE(HID_REPORT_ID(RID_REL_MOUSE))
+    };
+
+static const uint8_t hid_report_descriptor_auto[] = {
+    TUD_HID_REPORT_DESC_KEYBOARD(HID_REPORT_ID(RID_KEYBOARD)),
+    TUD_HID_REPORT_DESC_MOUSE_ABS(HID_REPORT_ID(RID_MOUSE)),
+    TUD_HID_REPORT_DESC_CONSUMER(HID_REPORT_ID(RID_CONSUMER_CONTROL)),
+    TUD_HID_REPORT_DESC_MOUSE(HID_REPORT_ID(RID_REL_MOUSE))
+    };
+
 /**
  * @brief Configuration descriptor
  *
  * This is a simple configuration descriptor that defines 1 configuration and 1 HID interface
  */
-static const uint8_t hid_configuration_descriptor[] = {
-    // Configuration number, interface count, string index, total length, attribute, power in mA
-    TUD_CONFIG_DESCRIPTOR(1, 1, 0, TUSB_DESC_TOTAL_LEN, TUSB_DESC_CONFIG_ATT_REMOTE_WAKEUP, 100),
+#define HID_CONFIGURATION_DESCRIPTOR(report_descriptor)                                                         \
+    {                                                                                                           \
+        /* Configuration number, interface count, string index, total length, attribute, power in mA */        \
+        TUD_CONFIG_DESCRIPTOR(1, 1, 0, TUSB_DESC_TOTAL_LEN, TUSB_DESC_CONFIG_ATT_REMOTE_WAKEUP, 100),           \
+        /* Interface number, string index, boot protocol, report descriptor len, EP In address, size & polling interval */ \
+        TUD_HID_DESCRIPTOR(0, 0, true, sizeof(report_descriptor), 0x81, 16, 1),                                 \
+    }
 
-    // Interface number, string index, boot protocol, report descriptor len, EP In address, size & polling interval
-    TUD_HID_DESCRIPTOR(0, 0, true, sizeof(hid_report_descriptor), 0x81, 16, 1),
-};
+static const uint8_t hid_configuration_descriptor_abs[] = HID_CONFIGURATION_DESCRIPTOR(hid_report_descriptor_abs);
+static const uint8_t hid_configuration_descriptor_rel[] = HID_CONFIGURATION_DESCRIPTOR(hid_report_descriptor_rel);
+static const uint8_t hid_configuration_descriptor_auto[] = HID_CONFIGURATION_DESCRIPTOR(hid_report_descriptor_auto);
+
+// Chosen by the mouse mode in `usb_util_init`
+static const uint8_t *hid_report_descriptor = hid_report_descriptor_abs;
+// The chosen configuration descriptor, without the remote wakeup attribute if it's disabled
+static uint8_t hid_configuration_descriptor[sizeof(hid_configuration_descriptor_abs)];
+
+// The defaults from the sdkconfig, in usb_descriptors.c
+extern tusb_desc_device_t descriptor_kconfig;
+extern tusb_desc_strarray_device_t descriptor_str_kconfig;
+
+// TinyUSB sends at most 31 characters of a string descriptor
+#define USB_STRING_SIZE 32
+
+// Passed by Rust, the strings are copied
+typedef struct
+{
+    uint16_t vid;
+    uint16_t pid;
+    const char *manufacturer;
+    const char *product;
+    const char *serial;
+} usb_util_identity_t;
+
+// TinyUSB keeps the pointers, they must outlive the driver
+static tusb_desc_device_t device_descriptor;
+static char manufacturer_string[USB_STRING_SIZE];
+static char product_string[USB_STRING_SIZE];
+static char serial_string[USB_STRING_SIZE];
+static const char *string_descriptor[USB_STRING_DESCRIPTOR_ARRAY_SIZE];
 
 /********* TinyUSB HID callbacks ***************/
 
@@ -118,11 +176,24 @@ uint16_t tud_hid_get_report_cb(uint8_t instance, uint8_t report_id, hid_report_t
     return 0;
 }
 
+// Implemented in Rust, receives the keyboard LEDs set by the host
+extern void usb_util_keyboard_leds_cb(uint8_t leds);
+
 // Invoked when received SET_REPORT control request or
 // received data on OUT endpoint ( Report ID = 0, Type = 0 )
 void tud_hid_set_report_cb(uint8_t instance, uint8_t report_id, hid_report_type_t report_type, uint8_t const *buffer,
                            uint16_t bufsize)
 {
+    (void)instance;
+
+    if (report_type != HID_REPORT_TYPE_OUTPUT || report_id != RID_KEYBOARD || bufsize == 0)
+    {
+        return;
+    }
+    // Some TinyUSB versions leave the report ID in the buffer
+    uint8_t leds = bufsize >= 2 ? buffer[1] : buffer[0];
+    ESP_LOGD(TAG, "Keyboard LEDs: %i", leds);
+    usb_util_keyboard_leds_cb(leds);
 }
 
 typedef struct TU_ATTR_PACKED
@@ -136,7 +207,149 @@ typedef struct TU_ATTR_PACKED
 
 static int initialized = 0;
 
-void usb_util_abs_mouse_report(uint8_t buttons, uint16_t x, uint16_t y, int8_t wheel, int8_t pan)
+// Return codes of the report functions, see `ReportError` in esparrier-core
+#define REPORT_OK 0
+#define REPORT_NOT_MOUNTED 1
+#define REPORT_SUSPENDED 2
+#define REPORT_QUEUE_FULL 3
+
+// The device states passed to `usb_util_state_cb`, see `UsbState` in esparrier-core
+#define USB_STATE_UNMOUNTED 0
+#define USB_STATE_MOUNTED 1
+#define USB_STATE_SUSPENDED 2
+
+#define REPORT_QUEUE_LENGTH 32
+// How long a report waits for room in the queue
+#define REPORT_QUEUE_TIMEOUT_MS 50
+// How long the sender waits for the endpoint before dropping a report
+#define REPORT_READY_TIMEOUT_MS 100
+// How long the reports queued after the remote wakeup wait for the host to resume
+#define WAKEUP_TIMEOUT_MS 2000
+
+typedef struct
+{
+    uint8_t report_id;
+    uint8_t len;
+    uint8_t data[8];
+} queued_report_t;
+
+static QueueHandle_t report_queue = NULL;
+static TaskHandle_t report_task_handle = NULL;
+// Set by the remote wakeup, the reports are queued instead of dropped until the host resumes or the timeout
+static volatile bool wakeup_requested = false;
+static volatile TickType_t wakeup_tick = 0;
+
+// Implemented in Rust, receives the mount and suspend changes
+extern void usb_util_state_cb(uint8_t state);
+
+void tud_mount_cb(void)
+{
+    usb_util_state_cb(USB_STATE_MOUNTED);
+}
+
+void tud_umount_cb(void)
+{
+    usb_util_state_cb(USB_STATE_UNMOUNTED);
+}
+
+void tud_suspend_cb(bool remote_wakeup_en)
+{
+    (void)remote_wakeup_en;
+    usb_util_state_cb(USB_STATE_SUSPENDED);
+}
+
+void tud_resume_cb(void)
+{
+    usb_util_state_cb(USB_STATE_MOUNTED);
+    // Flush the reports queued during the remote wakeup
+    if (report_task_handle != NULL)
+    {
+        xTaskNotifyGive(report_task_handle);
+    }
+}
+
+static bool wakeup_pending(void)
+{
+    return wakeup_requested && xTaskGetTickCount() - wakeup_tick < pdMS_TO_TICKS(WAKEUP_TIMEOUT_MS);
+}
+
+// Waits for the host woken up by the remote wakeup, without blocking the callers of the report functions
+static bool wait_resumed(void)
+{
+    while (tud_suspended())
+    {
+        TickType_t elapsed = xTaskGetTickCount() - wakeup_tick;
+        if (!wakeup_requested || elapsed >= pdMS_TO_TICKS(WAKEUP_TIMEOUT_MS))
+        {
+            return false;
+        }
+        // Notified by `tud_resume_cb`, or checks again when the timeout expires
+        ulTaskNotifyTake(pdTRUE, pdMS_TO_TICKS(WAKEUP_TIMEOUT_MS) - elapsed);
+    }
+    return true;
+}
+
+// Waits for the endpoint, gives up when the host goes away or doesn't poll in time
+static bool wait_ready(void)
+{
+    TickType_t start = xTaskGetTickCount();
+    while (!tud_hid_n_ready(0))
+    {
+        if (!tud_mounted() || tud_suspended() || xTaskGetTickCount() - start > pdMS_TO_TICKS(REPORT_READY_TIMEOUT_MS))
+        {
+            return false;
+        }
+        vTaskDelay(1);
+    }
+    return true;
+}
+
+static void report_task(void *arg)
+{
+    (void)arg;
+    queued_report_t report;
+    for (;;)
+    {
+        if (xQueueReceive(report_queue, &report, portMAX_DELAY) != pdTRUE)
+        {
+            continue;
+        }
+        if (!wait_resumed())
+        {
+            ESP_LOGW(TAG, "Report %i dropped, host didn't resume", report.report_id);
+            continue;
+        }
+        if (!wait_ready())
+        {
+            ESP_LOGW(TAG, "Report %i dropped, endpoint not ready", report.report_id);
+            continue;
+        }
+        tud_hid_n_report(0, report.report_id, report.data, report.len);
+    }
+}
+
+static int enqueue_report(uint8_t report_id, const void *data, uint8_t len)
+{
+    if (!tud_mounted())
+    {
+        return REPORT_NOT_MOUNTED;
+    }
+    bool suspended = tud_suspended();
+    if (suspended && !wakeup_pending())
+    {
+        return REPORT_SUSPENDED;
+    }
+    queued_report_t report = {.report_id = report_id, .len = len};
+    memcpy(report.data, data, len);
+    // The queue doesn't drain until the host resumes, so don't wait for room then
+    if (xQueueSend(report_queue, &report, suspended ? 0 : pdMS_TO_TICKS(REPORT_QUEUE_TIMEOUT_MS)) != pdTRUE)
+    {
+        return suspended ? REPORT_SUSPENDED : REPORT_QUEUE_FULL;
+    }
+    return REPORT_OK;
+}
+
+int usb_util_abs_mouse_report(uint8_t buttons, uint16_t x, uint16_t y, int8_t wheel, int8_t pan)
 {
     hid_abs_mouse_report_t report =
         {
@@ -148,52 +361,118 @@ void usb_util_abs_mouse_report(uint8_t buttons, uint16_t x, uint16_t y, int8_t w
     if (!initialized)
     {
         ESP_LOGI(TAG, "Buttons: %i, X: %i, Y: %i, Wheel: %i, Pan: %i", buttons, x, y, wheel, pan);
-        return;
+        return REPORT_OK;
     }
-    while(!tud_hid_n_ready(0));
-    tud_hid_n_report(0, HID_PROTOCOL_MOUSE, &report, sizeof(report));
-    while(!tud_hid_n_ready(0));
-    tud_hid_n_report(0, 0, NULL, 0);
+    return enqueue_report(RID_MOUSE, &report, sizeof(report));
 }
 
-void usb_util_keyboard_report(uint8_t modifier, uint8_t *key_report)
+int usb_util_rel_mouse_report(uint8_t buttons, int8_t x, int8_t y, int8_t wheel, int8_t pan)
+{
+    hid_mouse_report_t report =
+        {
+            .buttons = buttons,
+            .x = x,
+            .y = y,
+            .wheel = wheel,
+            .pan = pan};
+    if (!initialized)
+    {
+        ESP_LOGI(TAG, "Buttons: %i, dX: %i, dY: %i, Wheel: %i, Pan: %i", buttons, x, y, wheel, pan);
+        return REPORT_OK;
+    }
+    return enqueue_report(RID_REL_MOUSE, &report, sizeof(report));
+}
+
+int usb_util_keyboard_report(uint8_t modifier, uint8_t *key_report)
 {
     if (!initialized)
     {
         ESP_LOGI(TAG, "Modifier: %i, Button [%i, %i, %i, %i, %i, %i]", modifier, key_report[0], key_report[1], key_report[2], key_report[3], key_report[4], key_report[5]);
-        return;
+        return REPORT_OK;
+    }
+    hid_keyboard_report_t report = {.modifier = modifier, .reserved = 0};
+    memcpy(report.keycode, key_report, sizeof(report.keycode));
+    return enqueue_report(RID_KEYBOARD, &report, sizeof(report));
+}
+
+// Signals the suspended host to resume, false if the host is awake or didn't enable the remote wakeup. Returns
+// right away, the reports sent in the next `WAKEUP_TIMEOUT_MS` are queued and sent once the host resumes.
+bool usb_util_remote_wakeup(void)
+{
+    if (!initialized || !tud_remote_wakeup())
+    {
+        return false;
     }
-    while(!tud_hid_n_ready(0));
-    tud_hid_keyboard_report(HID_PROTOCOL_KEYBOARD, modifier, key_report);
-    while(!tud_hid_n_ready(0));
-    tud_hid_n_report(0, 0, NULL, 0);
+    wakeup_tick = xTaskGetTickCount();
+    wakeup_requested = true;
+    return true;
 }
 
-void usb_util_consumer_report(uint16_t code)
+int usb_util_consumer_report(uint16_t code)
 {
     if (!initialized)
     {
         ESP_LOGI(TAG, "Consumer code: %i", code);
-        return;
+        return REPORT_OK;
     }
-    // 
-    while(!tud_hid_n_ready(0));
-    tud_hid_n_report(0, RID_CONSUMER_CONTROL, &code, 2);
-    while(!tud_hid_n_ready(0));
-    tud_hid_n_report(0, 0, NULL, 0);
+    return enqueue_report(RID_CONSUMER_CONTROL, &code, sizeof(code));
 }
 
-void usb_util_init(void)
+void usb_util_init(uint8_t mouse_mode, bool remote_wakeup, const usb_util_identity_t *identity)
 {
-    ESP_LOGI(TAG, "USB initialization");
+    // Called on every connection to the server, the driver can only be installed once
+    if (initialized) {
+        return;
+    }
+    ESP_LOGI(TAG, "USB initialization, mouse mode %i, remote wakeup %i", mouse_mode, remote_wakeup);
+
+    const uint8_t *config_descriptor;
+    switch (mouse_mode)
+    {
+    case MOUSE_MODE_RELATIVE:
+        hid_report_descriptor = hid_report_descriptor_rel;
+        config_descriptor = hid_configuration_descriptor_rel;
+        break;
+    case MOUSE_MODE_AUTO:
+        hid_report_descriptor = hid_report_descriptor_auto;
+        config_descriptor = hid_configuration_descriptor_auto;
+        break;
+    default:
+        hid_report_descriptor = hid_report_descriptor_abs;
+        config_descriptor = hid_configuration_descriptor_abs;
+        break;
+    }
+
+    memcpy(hid_configuration_descriptor, config_descriptor, sizeof(hid_configuration_descriptor));
+    if (!remote_wakeup)
+    {
+        // bmAttributes of the configuration descriptor
+        hid_configuration_descriptor[7] &= ~TUSB_DESC_CONFIG_ATT_REMOTE_WAKEUP;
+    }
+
+    device_descriptor = descriptor_kconfig;
+    device_descriptor.idVendor = identity->vid;
+    device_descriptor.idProduct = identity->pid;
+    snprintf(manufacturer_string, sizeof(manufacturer_string), "%s", identity->manufacturer);
+    snprintf(product_string, sizeof(product_string), "%s", identity->product);
+    snprintf(serial_string, sizeof(serial_string), "%s", identity->serial);
+    memcpy(string_descriptor, descriptor_str_kconfig, sizeof(string_descriptor));
+    string_descriptor[1] = manufacturer_string;
+    string_descriptor[2] = product_string;
+    string_descriptor[3] = serial_string;
+    ESP_LOGI(TAG, "USB device %04x:%04x, %s %s, serial %s", identity->vid, identity->pid, manufacturer_string,
+             product_string, serial_string);
 
     const tinyusb_config_t tusb_cfg = {
-        .descriptor = NULL,
-        .string_descriptor = NULL,
+        .descriptor = &device_descriptor,
+        .string_descriptor = string_descriptor,
         .external_phy = false,
         .config_descriptor = hid_configuration_descriptor,
     };
 
+    report_queue = xQueueCreate(REPORT_QUEUE_LENGTH, sizeof(queued_report_t));
+    xTaskCreate(report_task, "usb_report", 2048, NULL, 5, &report_task_handle);
+
     ESP_ERROR_CHECK(tinyusb_driver_install(&tusb_cfg));
     ESP_LOGI(TAG, "USB initialization DONE");
     initialized = 1;